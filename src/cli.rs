@@ -0,0 +1,490 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+
+use crate::{
+    config::{Config, OperatingMode},
+    db::{
+        active_revision::ActiveRevision,
+        revision_files::RevisionFile,
+        revision_labels::RevisionLabel,
+        revision_routes::RevisionRoute,
+        revisions::{RevisionBuild, RevisionSummary},
+    },
+    diff::diff_revisions,
+    gc::{collect_garbage, Retention},
+    Result,
+};
+
+/// A speedy atomic static site generator.
+///
+/// Running without a command serves the site according to the configured `operating_mode`.
+#[derive(Debug, Parser)]
+#[clap(version, about)]
+pub struct Cli {
+    /// Config file to read settings from, environment variables and flags take precedence.
+    #[clap(short, long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Site to work on when several are configured, `serve` serves only this site
+    #[clap(long, global = true)]
+    pub site: Option<String>,
+    #[clap(flatten)]
+    pub config_args: ConfigArgs,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Flags overriding values from the config file and environment.
+#[derive(Debug, Args, Clone, Default)]
+pub struct ConfigArgs {
+    /// Directory for cached assets too large for the database
+    #[clap(long, global = true)]
+    pub cache_dir: Option<PathBuf>,
+    /// Path to the SQLite database
+    #[clap(long, global = true)]
+    pub db: Option<PathBuf>,
+    /// Directory the site is read from
+    #[clap(long, global = true)]
+    pub content_dir: Option<PathBuf>,
+    /// Show backtraces on internal server errors
+    #[clap(long, global = true)]
+    pub debug: bool,
+    /// Address to listen on, may be given multiple times
+    #[clap(long, global = true)]
+    pub listen: Vec<String>,
+    /// Unix domain socket to listen on
+    #[clap(long, global = true)]
+    pub unix_socket: Option<PathBuf>,
+    #[clap(skip)]
+    pub operating_mode: Option<OperatingMode>,
+    #[clap(skip)]
+    pub export_dir: Option<PathBuf>,
+    #[clap(skip)]
+    pub keep_revisions: Option<usize>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serve the site over HTTP
+    Serve {
+        /// Serve existing revisions without watching the content directory
+        #[clap(long)]
+        read_only: bool,
+    },
+    /// Walk the content directory once, build a revision from it and exit
+    Build,
+    /// Render a revision into a directory of static files
+    Export {
+        /// Revision to export, defaults to the one being served
+        #[clap(short, long)]
+        revision: Option<usize>,
+        /// Directory to write the files to
+        #[clap(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// Inspect the revisions stored in the database
+    Revisions {
+        #[clap(subcommand)]
+        command: RevisionsCommand,
+    },
+    /// Serve an earlier revision and stop following new builds until `unpin`
+    Rollback {
+        /// Revision to roll back to, defaults to the one before the one being served
+        revision: Option<usize>,
+    },
+    /// Keep serving a revision, ignoring new builds until `unpin`
+    Pin {
+        /// Revision to pin, defaults to the one being served
+        revision: Option<usize>,
+    },
+    /// Serve the latest revision and follow new builds again
+    Unpin,
+    /// Show what changed between two revisions
+    Diff {
+        /// Revision to compare from, defaults to the one before `to`
+        from: Option<usize>,
+        /// Revision to compare to, defaults to the one being served
+        to: Option<usize>,
+        /// Print the diff as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Delete old revisions and the files and cache blobs only they used
+    Gc {
+        /// How many of the newest revisions to keep, at least 1. Labelled and active revisions are
+        /// always kept
+        #[clap(short, long)]
+        keep: Option<usize>,
+        /// Report what would be removed without removing anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Validate the config and show where each value came from
+    Check,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RevisionsCommand {
+    /// List every revision
+    List,
+    /// Show the files and routes of a revision
+    Show {
+        /// Revision to show, defaults to the one being served
+        revision: Option<usize>,
+    },
+    /// Label a revision so it is never garbage collected
+    Label { revision: usize, label: String },
+    /// Remove a label from whichever revision has it
+    Unlabel { label: String },
+    /// List the latest builds, whether they succeeded or not, and why they failed
+    Builds {
+        /// How many builds to list
+        #[clap(long, default_value = "20")]
+        limit: usize,
+        /// Print the builds as JSON
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+impl Cli {
+    /// The config overrides from both the global flags and the command's own flags.
+    pub fn config_args(&self) -> ConfigArgs {
+        let mut args = self.config_args.clone();
+        match &self.command {
+            // Always serves, whatever mode is configured.
+            Some(Command::Serve { read_only }) => {
+                args.operating_mode = Some(if *read_only {
+                    OperatingMode::ReadOnly
+                } else {
+                    OperatingMode::ReadWrite
+                });
+            }
+            Some(Command::Build) => {
+                args.operating_mode = Some(OperatingMode::Build);
+            }
+            Some(Command::Export { out, .. }) => {
+                args.operating_mode = Some(OperatingMode::Export);
+                args.export_dir = out.clone();
+            }
+            Some(Command::Gc { keep, .. }) => {
+                args.keep_revisions = *keep;
+            }
+            _ => {}
+        }
+        args
+    }
+}
+
+pub fn revisions(command: &RevisionsCommand, pool: &Pool<SqliteConnectionManager>) -> Result<()> {
+    let conn = pool.get()?;
+    match command {
+        RevisionsCommand::List => {
+            println!(
+                "{:>8} {:>8} {:>8} {:>8}",
+                "REVISION", "FILES", "PAGES", "ROUTES"
+            );
+            let active = ActiveRevision::get(&conn)?;
+            let resolved = ActiveRevision::resolve(&conn)?;
+            let labels = RevisionLabel::all(&conn)?;
+            for summary in RevisionSummary::all(&conn)? {
+                let marker = match active {
+                    Some(a) if a.revision == summary.revision && a.pinned => " (active, pinned)",
+                    _ if resolved == Some(summary.revision) => " (active)",
+                    _ => "",
+                };
+                let labels = labels
+                    .iter()
+                    .filter(|l| l.revision == summary.revision)
+                    .map(|l| format!(" [{}]", l.label))
+                    .collect::<String>();
+                println!(
+                    "{:>8} {:>8} {:>8} {:>8}{}{}",
+                    summary.revision, summary.files, summary.pages, summary.routes, marker, labels
+                );
+            }
+        }
+        RevisionsCommand::Show { revision } => {
+            let revision = match revision {
+                Some(r) => *r,
+                None => ActiveRevision::resolve(&conn)?
+                    .ok_or_else(|| eyre::eyre!("There are no revisions yet"))?,
+            };
+            let summary = RevisionSummary::get(&conn, revision)?
+                .ok_or_else(|| eyre::eyre!("Revision {} does not exist", revision))?;
+
+            let active = ActiveRevision::resolve(&conn)?;
+            if active == Some(summary.revision) {
+                println!("Revision {} (active)", summary.revision);
+            } else {
+                println!("Revision {}", summary.revision);
+            }
+            println!(
+                "{} files, {} pages, {} routes",
+                summary.files, summary.pages, summary.routes
+            );
+            println!();
+            println!("Files:");
+            for (hash, path) in RevisionFile::for_revision(&conn, revision)? {
+                println!("  {} {}", hash, path);
+            }
+            println!();
+            println!("Routes:");
+            for route in RevisionRoute::for_revision(&conn, revision)? {
+                println!("  /{} ({:?} {})", route.route_path, route.kind, route.path);
+            }
+        }
+        RevisionsCommand::Label { revision, label } => {
+            if RevisionSummary::get(&conn, *revision)?.is_none() {
+                return Err(eyre::eyre!("Revision {} does not exist", revision));
+            }
+            RevisionLabel::set(&conn, *revision, label)?;
+            println!("Labelled revision {} as {:?}", revision, label);
+        }
+        RevisionsCommand::Unlabel { label } => {
+            if !RevisionLabel::remove(&conn, label)? {
+                return Err(eyre::eyre!("No revision is labelled {:?}", label));
+            }
+            println!("Removed label {:?}", label);
+        }
+        RevisionsCommand::Builds { limit, json } => {
+            let builds = RevisionBuild::recent(&conn, *limit)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&builds)?);
+                return Ok(());
+            }
+            println!(
+                "{:>8} {:<9} {:<24} {:>8} {:>8} {:>8} {:>8}",
+                "REVISION", "STATUS", "STARTED", "TIME", "CHANGES", "FILES", "ROUTES"
+            );
+            let or_dash = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+            for build in builds {
+                println!(
+                    "{:>8} {:<9} {:<24} {:>8} {:>8} {:>8} {:>8}",
+                    build.revision,
+                    build.status,
+                    build.started_at,
+                    or_dash(build.duration_ms.map(|d| format!("{}ms", d))),
+                    build.changes,
+                    or_dash(build.files.map(|f| f.to_string())),
+                    or_dash(build.routes.map(|r| r.to_string())),
+                );
+                for error in build.error.iter().chain(&build.page_errors) {
+                    for line in error.lines() {
+                        println!("{:>8} {}", "", line);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn diff(
+    from: Option<usize>,
+    to: Option<usize>,
+    json: bool,
+    pool: &Pool<SqliteConnectionManager>,
+) -> Result<()> {
+    let conn = pool.get()?;
+    let to = match to {
+        Some(r) => r,
+        None => ActiveRevision::resolve(&conn)?
+            .ok_or_else(|| eyre::eyre!("There are no revisions yet"))?,
+    };
+    let from = match from {
+        Some(r) => r,
+        None => previous_revision(&conn, to)?,
+    };
+    for revision in [from, to] {
+        if RevisionSummary::get(&conn, revision)?.is_none() {
+            return Err(eyre::eyre!("Revision {} does not exist", revision));
+        }
+    }
+
+    let diff = diff_revisions(&conn, from, to)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+
+    println!("Revision {} -> {}", diff.from, diff.to);
+    if diff.is_empty() {
+        println!("No changes");
+        return Ok(());
+    }
+    if !diff.files.is_empty() {
+        println!();
+        println!("Files:");
+        for file in &diff.files {
+            println!("  {} {}", file.kind.symbol(), file.path);
+        }
+    }
+    if !diff.routes.is_empty() {
+        println!();
+        println!("Routes:");
+        for route in &diff.routes {
+            println!(
+                "  {} /{} ({:?})",
+                route.kind.symbol(),
+                route.route_path,
+                route.route_kind
+            );
+        }
+    }
+    for page in &diff.pages {
+        println!();
+        println!("Page {} {}", page.kind.symbol(), page.path);
+        for field in &page.fields {
+            println!(
+                "  {}: {} -> {}",
+                field.field,
+                field.old.as_deref().unwrap_or("(none)"),
+                field.new.as_deref().unwrap_or("(none)")
+            );
+        }
+        if let Some(body) = &page.body {
+            println!();
+            print!("{}", body);
+        }
+    }
+    Ok(())
+}
+
+/// The newest revision older than `revision`.
+fn previous_revision(conn: &Connection, revision: usize) -> Result<usize> {
+    RevisionSummary::all(conn)?
+        .into_iter()
+        .map(|s| s.revision)
+        .rfind(|&r| r < revision)
+        .ok_or_else(|| eyre::eyre!("There is no revision before {}", revision))
+}
+
+pub fn rollback(revision: Option<usize>, pool: &Pool<SqliteConnectionManager>) -> Result<()> {
+    let conn = pool.get()?;
+    let revision = match revision {
+        Some(r) => r,
+        None => {
+            let active = ActiveRevision::resolve(&conn)?
+                .ok_or_else(|| eyre::eyre!("There are no revisions yet"))?;
+            previous_revision(&conn, active)?
+        }
+    };
+    pin(Some(revision), pool)
+}
+
+pub fn pin(revision: Option<usize>, pool: &Pool<SqliteConnectionManager>) -> Result<()> {
+    let conn = pool.get()?;
+    let revision = match revision {
+        Some(r) => r,
+        None => ActiveRevision::resolve(&conn)?
+            .ok_or_else(|| eyre::eyre!("There are no revisions yet"))?,
+    };
+    if RevisionSummary::get(&conn, revision)?.is_none() {
+        return Err(eyre::eyre!("Revision {} does not exist", revision));
+    }
+
+    ActiveRevision::set(&conn, revision, true)?;
+    println!("Serving revision {} (pinned)", revision);
+    Ok(())
+}
+
+pub fn unpin(pool: &Pool<SqliteConnectionManager>) -> Result<()> {
+    let conn = pool.get()?;
+    let latest: Option<usize> =
+        conn.query_row("SELECT MAX(revision) FROM revision_routes", [], |r| {
+            r.get(0)
+        })?;
+    let latest = latest.ok_or_else(|| eyre::eyre!("There are no revisions yet"))?;
+
+    ActiveRevision::set(&conn, latest, false)?;
+    println!("Serving revision {}, following new builds", latest);
+    Ok(())
+}
+
+pub fn check(config: &Config) -> Result<()> {
+    let sites = config.sites();
+    for (i, site) in sites.iter().enumerate() {
+        if sites.len() > 1 {
+            if i > 0 {
+                println!();
+            }
+            println!("Site {} ({})", site.name, site.hosts.join(", "));
+        }
+
+        let mut values = Vec::new();
+        flatten_value(None, serde_json::to_value(&*site.config)?, &mut values);
+
+        let width = values.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+        for (key, value) in &values {
+            let source = match key.split_once(".tls.") {
+                Some((prefix, _)) => site.config.source(&format!("{}.tls", prefix)),
+                None => site.config.source(key),
+            };
+            println!("{:width$} {} ({})", key, value, source, width = width);
+        }
+    }
+
+    let problems = config.check();
+    if problems.is_empty() {
+        println!();
+        println!("Config OK");
+        return Ok(());
+    }
+    println!();
+    for problem in &problems {
+        println!("error: {}", problem);
+    }
+    Err(eyre::eyre!("Config has {} problem(s)", problems.len()))
+}
+
+/// Flatten nested objects into dotted keys, e.g. `server.listen`.
+fn flatten_value(
+    prefix: Option<&str>,
+    value: serde_json::Value,
+    out: &mut Vec<(String, serde_json::Value)>,
+) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                let key = match prefix {
+                    Some(prefix) => format!("{}.{}", prefix, key),
+                    None => key,
+                };
+                flatten_value(Some(&key), value, out);
+            }
+        }
+        value => out.push((prefix.unwrap_or_default().to_string(), value)),
+    }
+}
+
+pub fn gc(config: &Config, pool: &Pool<SqliteConnectionManager>, dry_run: bool) -> Result<()> {
+    let retention = Retention {
+        keep_last: config.keep_revisions(),
+    };
+    let report = collect_garbage(config, pool, retention, dry_run)?;
+
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    println!("Kept revisions: {:?}", report.kept_revisions);
+    println!("{} revisions: {:?}", verb, report.removed_revisions);
+    println!(
+        "{} {} revision files, {} routes, {} stylesheets, {} build log entries",
+        verb,
+        report.revision_files,
+        report.revision_routes,
+        report.revision_stylesheets,
+        report.revision_builds
+    );
+    println!(
+        "{} {} input files ({} bytes), {} pages, {} page tags",
+        verb, report.input_files, report.input_file_bytes, report.pages, report.page_tags
+    );
+    println!(
+        "{} {} cache blobs ({} bytes)",
+        verb, report.cache_blobs, report.cache_bytes
+    );
+    Ok(())
+}
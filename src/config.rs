@@ -1,20 +1,105 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
     env::{self, VarError},
-    fs::File,
+    fmt,
+    fs::{self, File},
     path::{Path, PathBuf},
     str::FromStr,
+    sync::Arc,
 };
 
-use eyre::eyre;
+use eyre::{eyre, WrapErr};
+use rusqlite::{Connection, OpenFlags};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize)]
+use crate::{cli::ConfigArgs, date::Timezone, layout::Layout};
+
+#[derive(Debug, Serialize, Clone)]
 pub struct Config {
     cache_dir: PathBuf,
     db: PathBuf,
     debug: bool,
     content_dir: PathBuf,
     operating_mode: OperatingMode,
+    export_dir: PathBuf,
+    keep_revisions: usize,
+    timezone: Timezone,
+    preview: bool,
+    layout: Layout,
+    themes: Vec<PathBuf>,
+    ignore: Vec<String>,
+    coalesce_builds: bool,
+    server: ServerConfig,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    sites: Vec<SiteConfig>,
+    #[serde(skip)]
+    sources: BTreeMap<&'static str, Source>,
+}
+
+/// One of several sites served by the same process, overriding the top level config.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SiteConfig {
+    name: String,
+    hosts: Vec<String>,
+    cache_dir: PathBuf,
+    db: PathBuf,
+    content_dir: PathBuf,
+    #[serde(default = "none")]
+    export_dir: Option<PathBuf>,
+    #[serde(default = "none")]
+    preview: Option<bool>,
+    #[serde(default = "none")]
+    layout: Option<Layout>,
+    #[serde(default = "none")]
+    themes: Option<Vec<PathBuf>>,
+}
+
+/// A site and the config it is built and served with.
+#[derive(Debug, Clone)]
+pub struct Site {
+    pub name: String,
+    /// Hosts this site answers to, empty for the only site, which answers to every host.
+    pub hosts: Vec<String>,
+    pub config: Arc<Config>,
+}
+
+/// The layer a config value was taken from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(&'static str),
+    Args,
+    Site(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Args => write!(f, "command line"),
+            Source::Site(name) => write!(f, "site {}", name),
+        }
+    }
+}
+
+/// Where and how the HTTP server listens.
+#[derive(Debug, Serialize, Clone)]
+pub struct ServerConfig {
+    listen: Vec<String>,
+    unix_socket: Option<PathBuf>,
+    tls: Option<TlsConfig>,
+}
+
+/// Certificate and private key files, both PEM encoded.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
 }
 
 pub fn none<T>() -> Option<T> {
@@ -22,6 +107,7 @@ pub fn none<T>() -> Option<T> {
 }
 
 #[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct ConfigBuilder {
     #[serde(default = "none")]
     cache_dir: Option<PathBuf>,
@@ -33,6 +119,135 @@ pub struct ConfigBuilder {
     content_dir: Option<PathBuf>,
     #[serde(default = "none")]
     operating_mode: Option<OperatingMode>,
+    #[serde(default = "none")]
+    export_dir: Option<PathBuf>,
+    #[serde(default = "none")]
+    keep_revisions: Option<usize>,
+    #[serde(default = "none")]
+    timezone: Option<Timezone>,
+    #[serde(default = "none")]
+    preview: Option<bool>,
+    #[serde(default = "none")]
+    layout: Option<Layout>,
+    #[serde(default = "none")]
+    themes: Option<Vec<PathBuf>>,
+    #[serde(default = "none")]
+    ignore: Option<Vec<String>>,
+    #[serde(default = "none")]
+    coalesce_builds: Option<bool>,
+    #[serde(default)]
+    server: ServerConfigBuilder,
+    #[serde(default = "none")]
+    sites: Option<Vec<SiteConfig>>,
+    #[serde(skip)]
+    sources: BTreeMap<&'static str, Source>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfigBuilder {
+    #[serde(default = "none")]
+    listen: Option<Vec<String>>,
+    #[serde(default = "none")]
+    unix_socket: Option<PathBuf>,
+    #[serde(default = "none")]
+    tls: Option<TlsConfig>,
+}
+
+/// Read an environment variable, treating a missing one as unset.
+fn var(name: &str) -> crate::Result<Option<String>> {
+    match env::var(name) {
+        Ok(s) => Ok(Some(s)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(e) => Err(e).wrap_err_with(|| format!("Invalid value for {}", name)),
+    }
+}
+
+/// Read and parse an environment variable, naming it in the error if it can't be parsed.
+fn parse_var<T>(name: &str) -> crate::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    var(name)?
+        .map(|s| {
+            s.parse()
+                .wrap_err_with(|| format!("Invalid value for {}: {:?}", name, s))
+        })
+        .transpose()
+}
+
+/// Read a boolean environment variable, accepting `true`/`false`, `yes`/`no`, `on`/`off` and
+/// `1`/`0`.
+fn bool_var(name: &str) -> crate::Result<Option<bool>> {
+    var(name)?
+        .map(|s| match s.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(true),
+            "0" | "false" | "no" | "off" | "" => Ok(false),
+            _ => Err(eyre!(
+                "Invalid value for {}: {:?}, expected true or false",
+                name,
+                s
+            )),
+        })
+        .transpose()
+}
+
+impl ServerConfigBuilder {
+    fn with_envs(self) -> crate::Result<Self> {
+        let listen = var("LISTEN")?.map(|s| {
+            s.split(',')
+                .map(|a| a.trim().to_string())
+                .filter(|a| !a.is_empty())
+                .collect()
+        });
+        let unix_socket = var("UNIX_SOCKET")?.map(PathBuf::from);
+        let tls = match (env::var("TLS_CERT"), env::var("TLS_KEY")) {
+            (Ok(cert), Ok(key)) => Ok(Some(TlsConfig {
+                cert: PathBuf::from_str(&cert)?,
+                key: PathBuf::from_str(&key)?,
+            })),
+            (Err(VarError::NotPresent), Err(VarError::NotPresent)) => Ok(None),
+            (Err(VarError::NotUnicode(s)), _) | (_, Err(VarError::NotUnicode(s))) => {
+                Err(VarError::NotUnicode(s).into())
+            }
+            _ => Err(eyre!("TLS_CERT and TLS_KEY have to be set together")),
+        }?;
+        Ok(self.or(Self {
+            listen,
+            unix_socket,
+            tls,
+        }))
+    }
+
+    /// The keys of the values set in this builder.
+    fn keys(&self) -> Vec<&'static str> {
+        [
+            ("server.listen", self.listen.is_some()),
+            ("server.unix_socket", self.unix_socket.is_some()),
+            ("server.tls", self.tls.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(key, set)| set.then_some(key))
+        .collect()
+    }
+
+    fn or(self, other: Self) -> Self {
+        Self {
+            listen: other.listen.or(self.listen),
+            unix_socket: other.unix_socket.or(self.unix_socket),
+            tls: other.tls.or(self.tls),
+        }
+    }
+
+    fn build_with_defaults(self) -> ServerConfig {
+        let default_config = ServerConfig::default();
+        ServerConfig {
+            listen: self.listen.unwrap_or(default_config.listen),
+            unix_socket: self.unix_socket.or(default_config.unix_socket),
+            tls: self.tls.or(default_config.tls),
+        }
+    }
 }
 
 impl ConfigBuilder {
@@ -43,58 +258,131 @@ impl ConfigBuilder {
 
     /// Grab settings from environment variables, preferring them over the originals.
     pub fn with_envs(self) -> crate::Result<Self> {
-        let cache_dir = match env::var("CACHE_DIR") {
-            Ok(s) => Ok(Some(PathBuf::from_str(&s)?)),
-            Err(VarError::NotPresent) => Ok(None),
-            Err(e) => Err(e),
-        }?;
-        let db = match env::var("DB") {
-            Ok(s) => Ok(Some(PathBuf::from_str(&s)?)),
-            Err(VarError::NotPresent) => Ok(None),
-            Err(e) => Err(e),
-        }?;
-        let debug = match env::var("DEBUG") {
-            Ok(s) => {
-                let parsed: usize = s.parse()?;
-                Ok(Some(parsed == 1))
-            }
-            Err(VarError::NotPresent) => Ok(None),
-            Err(e) => Err(e),
-        }?;
-        let content_dir = match env::var("CONTENT_DIR") {
-            Ok(s) => Ok(Some(PathBuf::from_str(&s)?)),
-            Err(VarError::NotPresent) => Ok(None),
-            Err(e) => Err(e),
-        }?;
-        let operating_mode = match env::var("OPERATING_MODE") {
-            Ok(s) => Ok(Some(s.parse()?)),
-            Err(VarError::NotPresent) => Ok(None),
-            Err(e) => Err(e),
-        }?;
         let new = Self {
-            cache_dir,
-            db,
-            debug,
-            content_dir,
-            operating_mode,
+            cache_dir: var("CACHE_DIR")?.map(PathBuf::from),
+            db: var("DB")?.map(PathBuf::from),
+            debug: bool_var("DEBUG")?,
+            content_dir: var("CONTENT_DIR")?.map(PathBuf::from),
+            operating_mode: var("OPERATING_MODE")?
+                .map(|s| s.parse())
+                .transpose()
+                .wrap_err("Invalid value for OPERATING_MODE")?,
+            export_dir: var("EXPORT_DIR")?.map(PathBuf::from),
+            keep_revisions: parse_var("KEEP_REVISIONS")?,
+            timezone: parse_var("TIMEZONE")?,
+            preview: bool_var("PREVIEW")?,
+            layout: None,
+            themes: None,
+            ignore: None,
+            coalesce_builds: bool_var("COALESCE_BUILDS")?,
+            server: ServerConfigBuilder::default().with_envs()?,
+            sites: None,
+            sources: Default::default(),
         };
-        Ok(self.or(new))
+        let sources = new
+            .keys()
+            .into_iter()
+            .map(|key| (key, Source::Env(env_var_name(key))))
+            .collect();
+        Ok(self.or(new, sources))
     }
 
     /// Grab settings from a config file, preferring values from that over originals.
+    ///
+    /// Unknown keys are an error, so typos don't go unnoticed.
     pub fn with_file<P: AsRef<Path>>(self, file: P) -> crate::Result<Self> {
-        let new = serde_yaml::from_reader(File::open(file)?)?;
-        Ok(self.or(new))
+        let file = file.as_ref();
+        let new: Self = serde_yaml::from_reader(
+            File::open(file)
+                .wrap_err_with(|| format!("Failed to open config file {}", file.display()))?,
+        )
+        .wrap_err_with(|| format!("Invalid config file {}", file.display()))?;
+        let sources = new
+            .keys()
+            .into_iter()
+            .map(|key| (key, Source::File(file.to_path_buf())))
+            .collect();
+        Ok(self.or(new, sources))
     }
 
-    /// Merge two configs, using the settings from `other` if set otherwise `self`.
-    fn or(self, other: Self) -> Self {
+    /// Grab settings from command line flags, preferring them over the originals.
+    pub fn with_args(self, args: &ConfigArgs) -> Self {
+        let new = Self {
+            cache_dir: args.cache_dir.clone(),
+            db: args.db.clone(),
+            debug: args.debug.then_some(true),
+            content_dir: args.content_dir.clone(),
+            operating_mode: args.operating_mode,
+            export_dir: args.export_dir.clone(),
+            keep_revisions: args.keep_revisions,
+            timezone: None,
+            preview: None,
+            layout: None,
+            themes: None,
+            ignore: None,
+            coalesce_builds: None,
+            server: ServerConfigBuilder {
+                listen: (!args.listen.is_empty()).then(|| args.listen.clone()),
+                unix_socket: args.unix_socket.clone(),
+                tls: None,
+            },
+            sites: None,
+            sources: Default::default(),
+        };
+        let sources = new
+            .keys()
+            .into_iter()
+            .map(|key| (key, Source::Args))
+            .collect();
+        self.or(new, sources)
+    }
+
+    /// The keys of the values set in this builder.
+    fn keys(&self) -> Vec<&'static str> {
+        [
+            ("cache_dir", self.cache_dir.is_some()),
+            ("db", self.db.is_some()),
+            ("debug", self.debug.is_some()),
+            ("content_dir", self.content_dir.is_some()),
+            ("operating_mode", self.operating_mode.is_some()),
+            ("export_dir", self.export_dir.is_some()),
+            ("keep_revisions", self.keep_revisions.is_some()),
+            ("timezone", self.timezone.is_some()),
+            ("preview", self.preview.is_some()),
+            ("layout", self.layout.is_some()),
+            ("themes", self.themes.is_some()),
+            ("ignore", self.ignore.is_some()),
+            ("coalesce_builds", self.coalesce_builds.is_some()),
+            ("sites", self.sites.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(key, set)| set.then_some(key))
+        .chain(self.server.keys())
+        .collect()
+    }
+
+    /// Merge two configs, using the settings from `other` if set otherwise `self`, `sources` says
+    /// where the settings in `other` came from.
+    fn or(self, other: Self, sources: BTreeMap<&'static str, Source>) -> Self {
+        let mut merged_sources = self.sources;
+        merged_sources.extend(sources);
         Self {
             cache_dir: other.cache_dir.or(self.cache_dir),
             db: other.db.or(self.db),
             debug: other.debug.or(self.debug),
             content_dir: other.content_dir.or(self.content_dir),
             operating_mode: other.operating_mode.or(self.operating_mode),
+            export_dir: other.export_dir.or(self.export_dir),
+            keep_revisions: other.keep_revisions.or(self.keep_revisions),
+            timezone: other.timezone.or(self.timezone),
+            preview: other.preview.or(self.preview),
+            layout: other.layout.or(self.layout),
+            themes: other.themes.or(self.themes),
+            ignore: other.ignore.or(self.ignore),
+            coalesce_builds: other.coalesce_builds.or(self.coalesce_builds),
+            server: self.server.or(other.server),
+            sites: other.sites.or(self.sites),
+            sources: merged_sources,
         }
     }
 
@@ -107,10 +395,43 @@ impl ConfigBuilder {
             debug: self.debug.unwrap_or(false),
             content_dir: self.content_dir.unwrap_or(default_config.content_dir),
             operating_mode: self.operating_mode.unwrap_or(default_config.operating_mode),
+            export_dir: self.export_dir.unwrap_or(default_config.export_dir),
+            keep_revisions: self.keep_revisions.unwrap_or(default_config.keep_revisions),
+            timezone: self.timezone.unwrap_or(default_config.timezone),
+            preview: self.preview.unwrap_or(default_config.preview),
+            layout: self.layout.unwrap_or(default_config.layout),
+            themes: self.themes.unwrap_or(default_config.themes),
+            ignore: self.ignore.unwrap_or(default_config.ignore),
+            coalesce_builds: self
+                .coalesce_builds
+                .unwrap_or(default_config.coalesce_builds),
+            server: self.server.build_with_defaults(),
+            sites: self.sites.unwrap_or_default(),
+            sources: self.sources,
         }
     }
 }
 
+/// The environment variable a config key is read from.
+fn env_var_name(key: &str) -> &'static str {
+    match key {
+        "cache_dir" => "CACHE_DIR",
+        "db" => "DB",
+        "debug" => "DEBUG",
+        "content_dir" => "CONTENT_DIR",
+        "operating_mode" => "OPERATING_MODE",
+        "export_dir" => "EXPORT_DIR",
+        "keep_revisions" => "KEEP_REVISIONS",
+        "timezone" => "TIMEZONE",
+        "preview" => "PREVIEW",
+        "coalesce_builds" => "COALESCE_BUILDS",
+        "server.listen" => "LISTEN",
+        "server.unix_socket" => "UNIX_SOCKET",
+        "server.tls" => "TLS_CERT/TLS_KEY",
+        _ => unreachable!("{} is not read from the environment", key),
+    }
+}
+
 impl Config {
     pub fn cache_dir(&self) -> &Path {
         &self.cache_dir
@@ -131,6 +452,256 @@ impl Config {
     pub fn operating_mode(&self) -> OperatingMode {
         self.operating_mode
     }
+
+    pub fn export_dir(&self) -> &Path {
+        &self.export_dir
+    }
+
+    pub fn keep_revisions(&self) -> usize {
+        self.keep_revisions
+    }
+
+    /// The timezone of front matter dates written without an offset.
+    pub fn timezone(&self) -> Timezone {
+        self.timezone
+    }
+
+    pub fn preview(&self) -> bool {
+        self.preview
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Theme directories layered under the content directory, the first one having a file wins.
+    pub fn themes(&self) -> &[PathBuf] {
+        &self.themes
+    }
+
+    /// The content directory followed by the themes, in the order files are looked up in.
+    pub fn layers(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.content_dir.as_path()).chain(self.themes.iter().map(PathBuf::as_path))
+    }
+
+    /// Extra globs, in `.gitignore` syntax, of files to leave out of revisions.
+    pub fn ignore(&self) -> &[String] {
+        &self.ignore
+    }
+
+    /// Whether changes made while a revision is being built are built together afterwards,
+    /// instead of one revision each.
+    pub fn coalesce_builds(&self) -> bool {
+        self.coalesce_builds
+    }
+
+    pub fn server(&self) -> &ServerConfig {
+        &self.server
+    }
+
+    /// Which layer the value for `key` (e.g. `cache_dir` or `server.listen`) came from.
+    pub fn source(&self, key: &str) -> &Source {
+        self.sources.get(key).unwrap_or(&Source::Default)
+    }
+
+    /// Every site to serve, a config without `sites` is a single site answering to every host.
+    pub fn sites(&self) -> Vec<Site> {
+        if self.sites.is_empty() {
+            return vec![Site {
+                name: "default".to_string(),
+                hosts: Vec::new(),
+                config: Arc::new(self.clone()),
+            }];
+        }
+
+        self.sites
+            .iter()
+            .map(|site| {
+                let mut config = self.clone();
+                config.sites = Vec::new();
+                config.cache_dir = site.cache_dir.clone();
+                config.db = site.db.clone();
+                config.content_dir = site.content_dir.clone();
+                let mut keys = vec!["cache_dir", "db", "content_dir"];
+                if let Some(export_dir) = &site.export_dir {
+                    config.export_dir = export_dir.clone();
+                    keys.push("export_dir");
+                }
+                if let Some(preview) = site.preview {
+                    config.preview = preview;
+                    keys.push("preview");
+                }
+                if let Some(layout) = &site.layout {
+                    config.layout = layout.clone();
+                    keys.push("layout");
+                }
+                if let Some(themes) = &site.themes {
+                    config.themes = themes.clone();
+                    keys.push("themes");
+                }
+                for key in keys {
+                    config.sources.insert(key, Source::Site(site.name.clone()));
+                }
+
+                Site {
+                    name: site.name.clone(),
+                    hosts: site.hosts.iter().map(|h| h.to_ascii_lowercase()).collect(),
+                    config: Arc::new(config),
+                }
+            })
+            .collect()
+    }
+
+    /// The site named `name`, which may be left out if there is only one.
+    pub fn site(&self, name: Option<&str>) -> crate::Result<Site> {
+        let mut sites = self.sites();
+        match name {
+            Some(name) => sites
+                .into_iter()
+                .find(|s| s.name == name)
+                .ok_or_else(|| eyre!("There is no site named {:?}", name)),
+            None if sites.len() == 1 => Ok(sites.remove(0)),
+            None => Err(eyre!(
+                "{} sites are configured, pick one with --site ({})",
+                sites.len(),
+                sites
+                    .iter()
+                    .map(|s| s.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// Validate the paths of every site, returning a description of every problem found.
+    pub fn check(&self) -> Vec<String> {
+        if self.sites.is_empty() {
+            return self.check_paths();
+        }
+
+        let mut problems = Vec::new();
+        let mut names = BTreeSet::new();
+        let mut hosts = BTreeMap::new();
+        let mut paths = BTreeMap::new();
+        for site in self.sites() {
+            if !names.insert(site.name.clone()) {
+                problems.push(format!("site name {:?} is used more than once", site.name));
+            }
+            if site.hosts.is_empty() {
+                problems.push(format!("site {} has no hosts", site.name));
+            }
+            for host in &site.hosts {
+                if let Some(other) = hosts.insert(host.clone(), site.name.clone()) {
+                    problems.push(format!(
+                        "host {} is used by both site {} and site {}",
+                        host, other, site.name
+                    ));
+                }
+            }
+            for (key, path) in [
+                ("cache_dir", site.config.cache_dir()),
+                ("db", site.config.db()),
+                ("content_dir", site.config.content_dir()),
+            ] {
+                if let Some(other) = paths.insert(path.to_path_buf(), site.name.clone()) {
+                    problems.push(format!(
+                        "{} {} is shared by site {} and site {}",
+                        key,
+                        path.display(),
+                        other,
+                        site.name
+                    ));
+                }
+            }
+            problems.extend(
+                site.config
+                    .check_paths()
+                    .into_iter()
+                    .map(|p| format!("site {}: {}", site.name, p)),
+            );
+        }
+        problems
+    }
+
+    /// Validate the paths in this config, returning a description of every problem found.
+    fn check_paths(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if !self.content_dir.is_dir() {
+            problems.push(format!(
+                "content_dir {} is not a directory",
+                self.content_dir.display()
+            ));
+        }
+
+        for theme in &self.themes {
+            if !theme.is_dir() {
+                problems.push(format!("theme {} is not a directory", theme.display()));
+            }
+        }
+
+        if let Err(e) = check_writable(&self.cache_dir) {
+            problems.push(format!(
+                "cache_dir {} is not writable: {}",
+                self.cache_dir.display(),
+                e
+            ));
+        }
+
+        let db_result = if self.db.exists() {
+            let flags = match self.operating_mode {
+                OperatingMode::ReadOnly => OpenFlags::SQLITE_OPEN_READ_ONLY,
+                _ => OpenFlags::SQLITE_OPEN_READ_WRITE,
+            };
+            Connection::open_with_flags(&self.db, flags)
+                .and_then(|conn| conn.query_row("PRAGMA schema_version", [], |_| Ok(())))
+                .map_err(eyre::Report::from)
+        } else {
+            // The database is created on first run, as long as its directory can be written to.
+            check_writable(self.db.parent().unwrap_or_else(|| Path::new(".")))
+        };
+        if let Err(e) = db_result {
+            problems.push(format!("db {} can't be opened: {}", self.db.display(), e));
+        }
+
+        if let Some(tls) = &self.server.tls {
+            for (name, path) in [("cert", &tls.cert), ("key", &tls.key)] {
+                if !path.is_file() {
+                    problems.push(format!(
+                        "server.tls.{} {} is not a file",
+                        name,
+                        path.display()
+                    ));
+                }
+            }
+        }
+
+        problems
+    }
+}
+
+/// Check that files can be created in `dir`, or in its closest existing ancestor if it doesn't
+/// exist yet.
+fn check_writable(dir: &Path) -> crate::Result<()> {
+    let existing = dir
+        .ancestors()
+        .map(|p| {
+            if p.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                p
+            }
+        })
+        .find(|p| p.exists())
+        .ok_or_else(|| eyre!("no parent directory exists"))?;
+    if !existing.is_dir() {
+        return Err(eyre!("{} is not a directory", existing.display()));
+    }
+
+    let probe = existing.join(".emphasize-check");
+    File::create(&probe)?;
+    fs::remove_file(&probe)?;
+    Ok(())
 }
 
 impl Default for Config {
@@ -141,31 +712,66 @@ impl Default for Config {
             debug: false,
             content_dir: PathBuf::from_str("blog").unwrap(),
             operating_mode: Default::default(),
+            export_dir: PathBuf::from_str("public").unwrap(),
+            keep_revisions: 10,
+            timezone: Timezone::default(),
+            preview: false,
+            layout: Default::default(),
+            themes: Vec::new(),
+            ignore: Vec::new(),
+            coalesce_builds: true,
+            server: Default::default(),
+            sites: Vec::new(),
+            sources: Default::default(),
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
-pub enum OperatingMode {
-    ReadOnly,
-    ReadWrite,
+impl ServerConfig {
+    pub fn listen(&self) -> &[String] {
+        &self.listen
+    }
+
+    pub fn unix_socket(&self) -> Option<&Path> {
+        self.unix_socket.as_deref()
+    }
+
+    pub fn tls(&self) -> Option<&TlsConfig> {
+        self.tls.as_ref()
+    }
 }
 
-impl Default for OperatingMode {
+impl Default for ServerConfig {
     fn default() -> Self {
-        OperatingMode::ReadWrite
+        Self {
+            listen: vec!["0.0.0.0:8080".to_string()],
+            unix_socket: None,
+            tls: None,
+        }
     }
 }
 
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OperatingMode {
+    #[serde(alias = "Read")]
+    ReadOnly,
+    #[default]
+    ReadWrite,
+    Build,
+    Export,
+}
+
 impl FromStr for OperatingMode {
     type Err = eyre::Report;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "Read" => Ok(OperatingMode::ReadOnly),
+            "ReadOnly" | "Read" => Ok(OperatingMode::ReadOnly),
             "ReadWrite" => Ok(OperatingMode::ReadWrite),
+            "Build" => Ok(OperatingMode::Build),
+            "Export" => Ok(OperatingMode::Export),
             _ => Err(eyre!(format!(
-                "{} is not valid option (Read or ReadWrite)",
+                "{} is not valid option (ReadOnly, ReadWrite, Build or Export)",
                 s
             ))),
         }
@@ -0,0 +1,328 @@
+use std::{fmt, str::FromStr, time::SystemTime};
+
+use chrono::{
+    DateTime, Duration, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, Offset, SecondsFormat,
+    TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The timezone of dates written without an offset, either a fixed offset from UTC or a named
+/// timezone, which follows its daylight saving time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timezone {
+    Offset(FixedOffset),
+    Named(Tz),
+}
+
+impl Default for Timezone {
+    fn default() -> Self {
+        Self::Offset(FixedOffset::east(0))
+    }
+}
+
+impl Timezone {
+    /// Place a date and time in this timezone. Times that happen twice when the clocks go back
+    /// are the first one, times skipped when they go forward are moved forward as well.
+    fn to_utc(self, date: &NaiveDateTime) -> DateTime<Utc> {
+        let tz = match self {
+            Self::Offset(offset) => {
+                return Utc.from_utc_datetime(&(*date - offset_duration(offset)))
+            }
+            Self::Named(tz) => tz,
+        };
+        match tz.from_local_datetime(date) {
+            LocalResult::Single(date) | LocalResult::Ambiguous(date, _) => date.with_timezone(&Utc),
+            LocalResult::None => {
+                // The offset from before the clocks changed.
+                let offset = tz
+                    .offset_from_utc_datetime(&(*date - Duration::days(1)))
+                    .fix();
+                Utc.from_utc_datetime(&(*date - offset_duration(offset)))
+            }
+        }
+    }
+}
+
+fn offset_duration(offset: FixedOffset) -> Duration {
+    Duration::seconds(offset.local_minus_utc().into())
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid timezone {0:?}, expected UTC, an offset like +02:00 or a name like Europe/Paris")]
+pub struct InvalidTimezone(String);
+
+impl FromStr for Timezone {
+    type Err = InvalidTimezone;
+
+    /// `UTC`, `Z`, an offset like `+02:00` or a name from the tz database like `Europe/Paris`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("utc") || s == "Z" {
+            return Ok(Self::default());
+        }
+        let invalid = || InvalidTimezone(s.to_string());
+        let (sign, rest) = match s.as_bytes().first() {
+            Some(b'+') => (1, &s[1..]),
+            Some(b'-') => (-1, &s[1..]),
+            _ => return s.parse().map(Self::Named).map_err(|_| invalid()),
+        };
+        let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+        let hours: i32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+        Ok(Self::Offset(FixedOffset::east(
+            sign * (hours * 3600 + minutes * 60),
+        )))
+    }
+}
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Offset(offset) if offset.local_minus_utc() == 0 => f.write_str("UTC"),
+            Self::Offset(offset) => write!(f, "{}", offset),
+            Self::Named(tz) => f.write_str(tz.name()),
+        }
+    }
+}
+
+impl Serialize for Timezone {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timezone {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// A date from front matter, checked when it is read but only placed in a timezone once the
+/// config is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Date {
+    /// As written.
+    pub original: String,
+    parsed: Parsed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Parsed {
+    Offset(DateTime<FixedOffset>),
+    Local(NaiveDateTime),
+}
+
+impl Date {
+    /// Read RFC 3339 (`2022-05-03T10:00:00+02:00`), or a date and optionally a time without an
+    /// offset (`2022-05-03`, `2022-05-03 10:00`).
+    pub fn parse(s: &str) -> Option<Self> {
+        let trimmed = s.trim();
+        let parsed = if let Ok(date) = DateTime::parse_from_rfc3339(trimmed) {
+            Parsed::Offset(date)
+        } else if let Some(date) = [
+            "%Y-%m-%dT%H:%M:%S%.f",
+            "%Y-%m-%d %H:%M:%S%.f",
+            "%Y-%m-%d %H:%M",
+        ]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(trimmed, f).ok())
+        {
+            Parsed::Local(date)
+        } else {
+            Parsed::Local(
+                NaiveDate::parse_from_str(trimmed, "%Y-%m-%d")
+                    .ok()?
+                    .and_hms(0, 0, 0),
+            )
+        };
+        Some(Self {
+            original: s.to_string(),
+            parsed,
+        })
+    }
+
+    /// RFC 3339 in UTC, to the second so every date has the same width and they sort correctly as
+    /// text. Dates without an offset are taken to be in `timezone`.
+    pub fn normalized(&self, timezone: Timezone) -> String {
+        let utc = match &self.parsed {
+            Parsed::Offset(date) => date.with_timezone(&Utc),
+            Parsed::Local(date) => timezone.to_utc(date),
+        };
+        utc.to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+}
+
+impl From<SystemTime> for Date {
+    /// A file time, in UTC.
+    fn from(time: SystemTime) -> Self {
+        let date = DateTime::<Utc>::from(time);
+        Self {
+            original: date.to_rfc3339_opts(SecondsFormat::Secs, true),
+            parsed: Parsed::Offset(date.into()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Checked while the value is read, so errors point at it rather than at the front matter.
+        deserializer.deserialize_any(DateVisitor)
+    }
+}
+
+struct DateVisitor;
+
+impl<'de> de::Visitor<'de> for DateVisitor {
+    type Value = Date;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a date, like 2022-05-03")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Date, E> {
+        Date::parse(v).ok_or_else(|| {
+            E::custom(format!(
+                "invalid date {:?}, expected RFC 3339 (like 2022-05-03T10:00:00+02:00) or a date \
+                 like 2022-05-03",
+                v
+            ))
+        })
+    }
+
+    /// TOML has a date type of its own, which is read as written.
+    fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Date, A::Error> {
+        let date = toml::value::Datetime::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        self.visit_str(&date.to_string())
+    }
+}
+
+impl Serialize for Date {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.original)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{Date, Timezone};
+
+    fn normalized(date: &str, timezone: &str) -> String {
+        Date::parse(date)
+            .unwrap()
+            .normalized(timezone.parse().unwrap())
+    }
+
+    #[test]
+    fn offsets_are_kept() {
+        assert_eq!(
+            normalized("2022-05-03T10:00:00+02:00", "UTC"),
+            "2022-05-03T08:00:00Z"
+        );
+        // An explicit offset wins over the timezone.
+        assert_eq!(
+            normalized("2022-05-03T10:00:00Z", "+05:00"),
+            "2022-05-03T10:00:00Z"
+        );
+    }
+
+    #[test]
+    fn local_dates_are_in_the_timezone() {
+        assert_eq!(
+            normalized("2022-05-03T10:00:00", "UTC"),
+            "2022-05-03T10:00:00Z"
+        );
+        assert_eq!(
+            normalized("2022-05-03 10:00", "+02:00"),
+            "2022-05-03T08:00:00Z"
+        );
+        assert_eq!(
+            normalized("2022-05-03 10:00:30", "-01:30"),
+            "2022-05-03T11:30:30Z"
+        );
+    }
+
+    #[test]
+    fn dates_without_a_time_are_at_midnight() {
+        assert_eq!(normalized("2022-05-03", "UTC"), "2022-05-03T00:00:00Z");
+        assert_eq!(normalized("2022-05-03", "+02:00"), "2022-05-02T22:00:00Z");
+    }
+
+    #[test]
+    fn named_timezones_follow_daylight_saving_time() {
+        assert_eq!(
+            normalized("2022-01-10 12:00", "Europe/Paris"),
+            "2022-01-10T11:00:00Z"
+        );
+        assert_eq!(
+            normalized("2022-07-10 12:00", "Europe/Paris"),
+            "2022-07-10T10:00:00Z"
+        );
+        // Skipped when the clocks go forward, and twice when they go back.
+        assert_eq!(
+            normalized("2022-03-27 02:30", "Europe/Paris"),
+            "2022-03-27T01:30:00Z"
+        );
+        assert_eq!(
+            normalized("2022-10-30 02:30", "Europe/Paris"),
+            "2022-10-30T00:30:00Z"
+        );
+    }
+
+    #[test]
+    fn normalized_dates_have_the_same_width() {
+        assert_eq!(
+            normalized("2022-05-03T10:00:00.5Z", "UTC"),
+            "2022-05-03T10:00:00Z"
+        );
+        let modified = UNIX_EPOCH + Duration::new(1_651_572_000, 123_456_789);
+        assert_eq!(
+            Date::from(modified).normalized(Timezone::default()),
+            "2022-05-03T10:00:00Z"
+        );
+    }
+
+    #[test]
+    fn original_is_kept() {
+        assert_eq!(Date::parse("2022-05-03").unwrap().original, "2022-05-03");
+    }
+
+    #[test]
+    fn rejects_bad_dates() {
+        for date in [
+            "May 3",
+            "2022-13-01",
+            "2022-05-03T25:00:00Z",
+            "03/05/2022",
+            "",
+        ] {
+            assert!(Date::parse(date).is_none(), "{:?} was parsed", date);
+        }
+    }
+
+    #[test]
+    fn parses_timezones() {
+        for (timezone, shown) in [
+            ("UTC", "UTC"),
+            ("Z", "UTC"),
+            ("+02:00", "+02:00"),
+            ("-05:30", "-05:30"),
+            ("Europe/Paris", "Europe/Paris"),
+        ] {
+            assert_eq!(timezone.parse::<Timezone>().unwrap().to_string(), shown);
+        }
+        for timezone in ["", "Mars/Olympus", "+25:00", "+02:61", "2"] {
+            assert!(
+                timezone.parse::<Timezone>().is_err(),
+                "{:?} was parsed",
+                timezone
+            );
+        }
+    }
+}
@@ -10,12 +10,15 @@ use crate::Result;
 
 use self::migrations::Migration;
 
+pub mod active_revision;
 pub mod input_files;
 pub mod migrations;
 pub mod pages;
 pub mod revision_files;
+pub mod revision_labels;
 pub mod revision_routes;
 pub mod revision_stylesheet;
+pub mod revisions;
 
 type Pool = r2d2::Pool<SqliteConnectionManager>;
 type InsertStmt<'a, T> = dyn for<'i> FnMut(&'i <T as Insertable>::I<'i>) -> Result<()> + 'a;
@@ -35,7 +38,7 @@ pub trait Insertable {
             Ok(())
         })
     }
-    fn raw_stmt(db: &Connection) -> Result<Statement>;
+    fn raw_stmt(db: &Connection) -> Result<Statement<'_>>;
 
     fn prepare_insert<'a>(db: &'a Connection) -> Result<Box<InsertStmt<'a, Self>>>
     where
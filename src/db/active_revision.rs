@@ -0,0 +1,127 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+use super::migrations::Migration;
+
+/// The revision being served.
+///
+/// Unless `pinned`, the pointer follows every newly built revision. A pinned pointer stays put
+/// until it is moved explicitly, which is how rollbacks work.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ActiveRevision {
+    pub revision: usize,
+    pub pinned: bool,
+}
+
+impl ActiveRevision {
+    /// The stored pointer, if one has been set.
+    pub fn get(db: &Connection) -> Result<Option<Self>> {
+        let active = db
+            .query_row(
+                "SELECT revision, pinned FROM active_revision WHERE id = 0",
+                [],
+                |r| {
+                    Ok(Self {
+                        revision: r.get(0)?,
+                        pinned: r.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(active)
+    }
+
+    /// The revision that should be served, falling back to the newest one when no pointer has
+    /// been stored yet.
+    pub fn resolve(db: &Connection) -> Result<Option<usize>> {
+        match Self::get(db)? {
+            Some(active) => Ok(Some(active.revision)),
+            None => Ok(
+                db.query_row("SELECT MAX(revision) FROM revision_routes", [], |r| {
+                    r.get(0)
+                })?,
+            ),
+        }
+    }
+
+    /// Point at `revision`, regardless of whether the pointer is pinned.
+    pub fn set(db: &Connection, revision: usize, pinned: bool) -> Result<()> {
+        db.execute(
+            "INSERT OR REPLACE INTO active_revision VALUES (0, ?1, ?2)",
+            params![revision, pinned],
+        )?;
+        Ok(())
+    }
+
+    /// Move the pointer to a freshly built revision, unless it is pinned or already at a newer
+    /// one. Revision numbers are handed out before building, so a slow build can finish after a
+    /// newer one did.
+    ///
+    /// Returns `true` if the pointer moved.
+    pub fn advance(db: &Connection, revision: usize) -> Result<bool> {
+        let changed = db.execute(
+            "
+            INSERT INTO active_revision VALUES (0, ?1, FALSE)
+            ON CONFLICT (id) DO UPDATE SET revision = excluded.revision
+            WHERE NOT pinned AND excluded.revision > active_revision.revision
+        ",
+            params![revision],
+        )?;
+        Ok(changed > 0)
+    }
+}
+
+impl Migration for ActiveRevision {
+    fn migrate(db: &Connection) -> Result<()> {
+        log::trace!("Creating ActiveRevision...");
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS active_revision (
+            id INT PRIMARY KEY CHECK (id = 0),
+            revision INT,
+            pinned BOOLEAN
+        );",
+            [],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::ActiveRevision;
+    use crate::db::migrations::Migration;
+
+    fn db() -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        ActiveRevision::migrate(&db).unwrap();
+        db
+    }
+
+    #[test]
+    fn advances_only_forward() {
+        let db = db();
+        assert!(ActiveRevision::advance(&db, 2).unwrap());
+        assert!(!ActiveRevision::advance(&db, 1).unwrap());
+        assert_eq!(ActiveRevision::resolve(&db).unwrap(), Some(2));
+        assert!(ActiveRevision::advance(&db, 3).unwrap());
+        assert_eq!(ActiveRevision::resolve(&db).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn pinned_pointers_stay_put() {
+        let db = db();
+        ActiveRevision::set(&db, 2, true).unwrap();
+        assert!(!ActiveRevision::advance(&db, 3).unwrap());
+        assert_eq!(
+            ActiveRevision::get(&db).unwrap(),
+            Some(ActiveRevision {
+                revision: 2,
+                pinned: true
+            })
+        );
+    }
+}
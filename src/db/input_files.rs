@@ -1,5 +1,5 @@
 use rusqlite::Connection;
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Serialize};
 
 use crate::Result;
 
@@ -11,7 +11,7 @@ pub struct InputFile {
     pub path: String,
     pub contents: Vec<u8>,
     pub size: i64,
-    pub inline: bool
+    pub inline: bool,
 }
 
 #[derive(Clone, Serialize)]
@@ -20,12 +20,12 @@ pub struct InputFileIn<'a> {
     pub path: &'a str,
     pub contents: &'a [u8],
     pub size: i64,
-    pub inline: bool
+    pub inline: bool,
 }
 
 impl Insertable for InputFile {
     type I<'i> = InputFileIn<'i>;
-    fn raw_stmt(db: &Connection) -> Result<rusqlite::Statement> {
+    fn raw_stmt(db: &Connection) -> Result<rusqlite::Statement<'_>> {
         let r = db.prepare(
             "INSERT OR IGNORE INTO input_files VALUES (:hash, :path, :contents, :size, :inline);",
         )?;
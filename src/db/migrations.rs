@@ -34,3 +34,21 @@ where
         Ok(())
     }
 }
+
+/// Add a column to a table created before the column existed, returns `true` if it was added.
+pub fn add_column(db: &Connection, table: &str, column: &str, kind: &str) -> crate::Result<bool> {
+    let exists = db
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1",
+            table
+        ))?
+        .exists([column])?;
+    if !exists {
+        log::info!("Adding {} to {}...", column, table);
+        db.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, kind),
+            [],
+        )?;
+    }
+    Ok(!exists)
+}
@@ -1,21 +1,41 @@
 use rusqlite::{params, Connection};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
 use serde_rusqlite::{from_rows, to_params_named};
 
-use crate::{db::Insertable, Result};
+use crate::{
+    date::{Date, Timezone},
+    db::Insertable,
+    frontmatter::FrontMatter,
+    Result,
+};
 
-use super::migrations::Migration;
+use super::migrations::{add_column, Migration};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct Page {
     pub hash: String,
     pub path: String,
     pub title: String,
+    /// RFC 3339, in UTC.
     pub date: String,
+    /// The date as written in the front matter.
+    pub date_original: String,
     pub content_offset: usize,
     pub template: Option<String>,
     pub route_path: String,
     pub draft: bool,
+    /// Front matter keys other than the ones above.
+    #[serde(deserialize_with = "json_object")]
+    pub extra: Map<String, Value>,
+}
+
+/// A JSON object stored as text, pages stored before `extra` existed have none.
+fn json_object<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Map<String, Value>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(json) => serde_json::from_str(&json).map_err(serde::de::Error::custom),
+        None => Ok(Map::new()),
+    }
 }
 
 impl Page {
@@ -43,18 +63,20 @@ pub struct PageIn<'a> {
     pub path: &'a str,
     pub title: &'a str,
     pub date: &'a str,
+    pub date_original: &'a str,
     pub tags: &'a Vec<String>,
     pub content_offset: usize,
     pub route_path: &'a str,
     pub template: &'a Option<String>,
     pub draft: bool,
+    pub extra: &'a Map<String, Value>,
 }
 
 impl Insertable for Page {
     type I<'i> = PageIn<'i>;
-    fn raw_stmt(db: &rusqlite::Connection) -> Result<rusqlite::Statement> {
+    fn raw_stmt(db: &rusqlite::Connection) -> Result<rusqlite::Statement<'_>> {
         let r =
-            db.prepare("INSERT OR IGNORE INTO pages VALUES (:hash, :path, :title, :date, :tags, :content_offset, :template, :route_path, :draft);")?;
+            db.prepare("INSERT OR IGNORE INTO pages VALUES (:hash, :path, :title, :date, :tags, :content_offset, :template, :route_path, :draft, :extra, :date_original);")?;
         Ok(r)
     }
     fn with_insert<F, O>(db: &rusqlite::Connection, mut callback: F) -> Result<O>
@@ -79,11 +101,13 @@ impl Insertable for Page {
             pub path: &'a str,
             pub title: &'a str,
             pub date: &'a str,
+            pub date_original: &'a str,
             pub tags: &'a str,
             pub content_offset: usize,
             pub route_path: &'a str,
             pub template: &'a Option<String>,
             pub draft: bool,
+            pub extra: &'a str,
         }
 
         #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -105,11 +129,13 @@ impl Insertable for Page {
                 path: input.path,
                 title: input.title,
                 date: input.date,
+                date_original: input.date_original,
                 tags: &serde_json::to_string(input.tags)?,
                 content_offset: input.content_offset,
                 route_path: input.route_path,
                 template: input.template,
                 draft: input.draft,
+                extra: &serde_json::to_string(input.extra)?,
             };
 
             pages_stmt.execute(to_params_named(&pin)?.to_slice().as_slice())?;
@@ -143,11 +169,54 @@ impl Migration for Page {
             template VARCHAR,
             route_path VARCHAR,
             draft BOOLEAN,
+            extra JSON,
+            date_original VARCHAR,
             PRIMARY KEY(hash, path),
             FOREIGN KEY (hash, path) REFERENCES input_files
         );",
             [],
         )?;
+
+        // Stored pages are shared by every revision they are in and never stored again, so what
+        // they were stored without is filled in once, here.
+        if add_column(db, "pages", "extra", "JSON")? {
+            log::info!("Reading the extra front matter keys of stored pages...");
+            let pages = db
+                .prepare(
+                    "SELECT hash, path, input_files.contents
+                    FROM pages JOIN input_files USING (hash, path)",
+                )?
+                .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?
+                .collect::<rusqlite::Result<Vec<(String, String, Vec<u8>)>>>()?;
+            let mut update =
+                db.prepare("UPDATE pages SET extra = ?3 WHERE hash = ?1 AND path = ?2")?;
+            for (hash, path, contents) in pages {
+                // Pages that can't be read anymore keep no extra keys.
+                let parsed = std::str::from_utf8(&contents)
+                    .ok()
+                    .and_then(|contents| FrontMatter::parse(&path, contents).ok());
+                if let Some((fm, _)) = parsed {
+                    update.execute(params![hash, path, serde_json::to_string(&fm.extra)?])?;
+                }
+            }
+        }
+        if add_column(db, "pages", "date_original", "VARCHAR")? {
+            log::info!("Normalizing page dates...");
+            // Dates were stored as written back then. The timezone isn't known here, dates
+            // without an offset are taken to be in UTC.
+            db.execute("UPDATE pages SET date_original = date", [])?;
+            let dates = db
+                .prepare("SELECT hash, path, date FROM pages")?
+                .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?
+                .collect::<rusqlite::Result<Vec<(String, String, String)>>>()?;
+            let mut update =
+                db.prepare("UPDATE pages SET date = ?3 WHERE hash = ?1 AND path = ?2")?;
+            for (hash, path, date) in dates {
+                if let Some(date) = Date::parse(&date) {
+                    update.execute(params![hash, path, date.normalized(Timezone::default())])?;
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -170,3 +239,57 @@ impl Migration for PageTag {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::{params, Connection};
+    use serde_rusqlite::from_rows;
+
+    use super::Page;
+    use crate::db::{input_files::InputFile, migrations::Migration};
+
+    #[test]
+    fn pages_stored_before_extra_and_dates_are_filled_in() {
+        let db = Connection::open_in_memory().unwrap();
+        InputFile::migrate(&db).unwrap();
+        // As pages were stored before `extra` and `date_original` existed.
+        db.execute(
+            "CREATE TABLE pages (
+            hash CHAR(16), path VARCHAR, title VARCHAR, date DATE, tags JSON, content_offset INT,
+            template VARCHAR, route_path VARCHAR, draft BOOLEAN, PRIMARY KEY(hash, path)
+        );",
+            [],
+        )
+        .unwrap();
+        let contents = "---\ntitle: Hello\ndate: 2022-05-03 10:00\nweight: 2\n---\nBody\n";
+        db.execute(
+            "INSERT INTO input_files VALUES ('h', 'content/a.md', ?1, ?2, TRUE)",
+            params![contents.as_bytes(), contents.len()],
+        )
+        .unwrap();
+        db.execute(
+            "INSERT INTO pages VALUES
+            ('h', 'content/a.md', 'Hello', '2022-05-03 10:00', '[]', 55, NULL, 'a', FALSE)",
+            [],
+        )
+        .unwrap();
+
+        // Migrating again leaves them as they are.
+        for _ in 0..2 {
+            Page::migrate(&db).unwrap();
+            let page = from_rows::<Page>(
+                db.prepare("SELECT * FROM pages")
+                    .unwrap()
+                    .query([])
+                    .unwrap(),
+            )
+            .next()
+            .unwrap()
+            .unwrap();
+            assert_eq!(page.extra["weight"], 2);
+            assert_eq!(page.extra.len(), 1);
+            assert_eq!(page.date, "2022-05-03T10:00:00Z");
+            assert_eq!(page.date_original, "2022-05-03 10:00");
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use rusqlite::Connection;
+use fallible_iterator::FallibleIterator;
+use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
 
 use super::{migrations::Migration, Insertable};
@@ -12,9 +13,28 @@ pub struct RevisionFileIn<'a> {
 
 pub struct RevisionFile;
 
+impl RevisionFile {
+    /// The `(hash, path)` pairs making up a revision, sorted by path.
+    pub fn for_revision(db: &Connection, rev_id: usize) -> crate::Result<Vec<(String, String)>> {
+        let mut stmt = db.prepare(
+            "
+            SELECT hash, path FROM revision_files
+            WHERE revision = ?1
+            ORDER BY path
+        ",
+        )?;
+        let rows = stmt
+            .query(params![rev_id])?
+            .map(|r| Ok((r.get(0)?, r.get(1)?)))
+            .collect()?;
+
+        Ok(rows)
+    }
+}
+
 impl Insertable for RevisionFile {
     type I<'i> = RevisionFileIn<'i>;
-    fn raw_stmt(db: &rusqlite::Connection) -> crate::Result<rusqlite::Statement> {
+    fn raw_stmt(db: &rusqlite::Connection) -> crate::Result<rusqlite::Statement<'_>> {
         let r =
             db.prepare("INSERT OR IGNORE INTO revision_files VALUES (:hash, :path, :revision);")?;
         Ok(r)
@@ -0,0 +1,57 @@
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_rows;
+
+use crate::Result;
+
+use super::migrations::Migration;
+
+/// A name given to a revision, labelled revisions are never garbage collected.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct RevisionLabel {
+    pub revision: usize,
+    pub label: String,
+}
+
+impl RevisionLabel {
+    pub fn all(db: &Connection) -> Result<Vec<Self>> {
+        let mut stmt = db.prepare("SELECT * FROM revision_labels ORDER BY revision, label")?;
+        let rows = from_rows::<Self>(stmt.query([])?)
+            .map(|r| r.map_err(|e| e.into()))
+            .collect::<Result<Vec<Self>>>()?;
+
+        Ok(rows)
+    }
+
+    /// Give `revision` the label, moving it if another revision had it.
+    pub fn set(db: &Connection, revision: usize, label: &str) -> Result<()> {
+        db.execute(
+            "INSERT OR REPLACE INTO revision_labels VALUES (?1, ?2)",
+            params![revision, label],
+        )?;
+        Ok(())
+    }
+
+    /// Remove a label, returns `false` if it didn't exist.
+    pub fn remove(db: &Connection, label: &str) -> Result<bool> {
+        let removed = db.execute(
+            "DELETE FROM revision_labels WHERE label = ?1",
+            params![label],
+        )?;
+        Ok(removed > 0)
+    }
+}
+
+impl Migration for RevisionLabel {
+    fn migrate(db: &Connection) -> Result<()> {
+        log::trace!("Creating RevisionLabel...");
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS revision_labels (
+            revision INT,
+            label VARCHAR PRIMARY KEY
+        );",
+            [],
+        )?;
+        Ok(())
+    }
+}
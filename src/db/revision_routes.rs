@@ -1,6 +1,8 @@
 use num_enum::TryFromPrimitive;
+use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use serde_rusqlite::from_rows;
 
 use super::{migrations::Migration, Insertable};
 
@@ -36,9 +38,27 @@ pub struct RevisionRoute {
     pub template: Option<String>,
 }
 
+impl RevisionRoute {
+    pub fn for_revision(db: &Connection, rev_id: usize) -> crate::Result<Vec<Self>> {
+        let mut stmt = db.prepare(
+            "
+            SELECT *
+            FROM revision_routes
+            WHERE revision = ?1
+            ORDER BY route_path
+        ",
+        )?;
+        let rows = from_rows::<Self>(stmt.query(params![rev_id])?)
+            .map(|r| r.map_err(|e| e.into()))
+            .collect::<crate::Result<Vec<Self>>>()?;
+
+        Ok(rows)
+    }
+}
+
 impl Insertable for RevisionRoute {
     type I<'i> = RevisionRouteIn<'i>;
-    fn raw_stmt(db: &rusqlite::Connection) -> crate::Result<rusqlite::Statement> {
+    fn raw_stmt(db: &rusqlite::Connection) -> crate::Result<rusqlite::Statement<'_>> {
         let r = db.prepare(
             "INSERT INTO revision_routes VALUES (:revision, :route_path, :parent_route_path, :kind, :hash, :path, :template);"
         )?;
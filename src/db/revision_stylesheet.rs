@@ -18,7 +18,7 @@ pub struct RevisionStylesheetIn<'a> {
 
 impl Insertable for RevisionStylesheet {
     type I<'i> = RevisionStylesheetIn<'i>;
-    fn raw_stmt(db: &rusqlite::Connection) -> crate::Result<rusqlite::Statement> {
+    fn raw_stmt(db: &rusqlite::Connection) -> crate::Result<rusqlite::Statement<'_>> {
         let r = db.prepare(
             "INSERT OR IGNORE INTO revision_stylesheets VALUES (:revision, :name, :data);",
         )?;
@@ -0,0 +1,268 @@
+use std::time::Duration;
+
+use rusqlite::{params, Connection, TransactionBehavior};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_rusqlite::from_rows;
+
+use crate::Result;
+
+use super::migrations::{add_column, Migration};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RevisionSummary {
+    pub revision: usize,
+    pub files: usize,
+    pub pages: usize,
+    pub routes: usize,
+}
+
+impl RevisionSummary {
+    const SELECT: &'static str = "
+        SELECT
+            revision_files.revision AS revision,
+            COUNT(*) AS files,
+            COUNT(pages.hash) AS pages,
+            (
+                SELECT COUNT(*) FROM revision_routes
+                WHERE revision_routes.revision = revision_files.revision
+            ) AS routes
+        FROM revision_files
+        LEFT JOIN pages
+        ON pages.hash = revision_files.hash AND pages.path = revision_files.path
+    ";
+
+    /// Summaries of every revision, oldest first.
+    pub fn all(db: &Connection) -> Result<Vec<Self>> {
+        let mut stmt = db.prepare(&format!(
+            "{} GROUP BY revision_files.revision ORDER BY revision_files.revision",
+            Self::SELECT
+        ))?;
+        let rows = from_rows::<Self>(stmt.query([])?)
+            .map(|r| r.map_err(|e| e.into()))
+            .collect::<Result<Vec<Self>>>()?;
+
+        Ok(rows)
+    }
+
+    /// Summary of a single revision, if it exists.
+    pub fn get(db: &Connection, rev_id: usize) -> Result<Option<Self>> {
+        let mut stmt = db.prepare(&format!(
+            "{} WHERE revision_files.revision = ?1 GROUP BY revision_files.revision",
+            Self::SELECT
+        ))?;
+        let row = from_rows::<Self>(stmt.query(params![rev_id])?)
+            .next()
+            .transpose()?;
+
+        Ok(row)
+    }
+}
+
+/// How far a build got.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildStatus {
+    Building,
+    Succeeded,
+    Failed,
+}
+
+impl std::fmt::Display for BuildStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Self::Building => "building",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+        })
+    }
+}
+
+/// What a build measured so far, recorded whether it succeeded or not.
+#[derive(Debug, Default, Clone)]
+pub struct BuildMetrics {
+    pub input_files_ms: Option<u64>,
+    pub routes_ms: Option<u64>,
+    pub stylesheets_ms: Option<u64>,
+    pub files: Option<usize>,
+    pub routes: Option<usize>,
+    /// Why pages were left out of the revision, the rest of it is built without them.
+    pub page_errors: Vec<String>,
+}
+
+/// The log entry of one attempt at building a revision.
+///
+/// Failed builds keep their revision number, so the numbers of built revisions may have gaps.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct RevisionBuild {
+    pub revision: usize,
+    pub status: BuildStatus,
+    /// UTC, ISO 8601.
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub input_files_ms: Option<u64>,
+    pub routes_ms: Option<u64>,
+    pub stylesheets_ms: Option<u64>,
+    /// Changed files the build was started for.
+    pub changes: usize,
+    /// Files in the revision.
+    pub files: Option<usize>,
+    pub routes: Option<usize>,
+    pub error: Option<String>,
+    /// Pages left out of the revision because they couldn't be read, with why.
+    #[serde(default, deserialize_with = "json_list")]
+    pub page_errors: Vec<String>,
+}
+
+/// A list stored as a JSON array, or NULL for an empty one.
+fn json_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(list) => serde_json::from_str(&list).map_err(de::Error::custom),
+        None => Ok(Vec::new()),
+    }
+}
+
+const NOW: &str = "strftime('%Y-%m-%dT%H:%M:%fZ', 'now')";
+
+/// How long a build may run before it is taken for interrupted.
+const BUILD_LEASE: Duration = Duration::from_secs(60 * 60);
+
+impl RevisionBuild {
+    /// The newest builds, newest first.
+    pub fn recent(db: &Connection, limit: usize) -> Result<Vec<Self>> {
+        let mut stmt = db.prepare("SELECT * FROM revisions ORDER BY revision DESC LIMIT ?1")?;
+        let rows = from_rows::<Self>(stmt.query(params![limit])?)
+            .map(|r| r.map_err(|e| e.into()))
+            .collect::<Result<Vec<Self>>>()?;
+
+        Ok(rows)
+    }
+
+    /// The newest build that is no longer running, if any.
+    pub fn latest_finished(db: &Connection) -> Result<Option<Self>> {
+        let mut stmt = db.prepare(
+            "SELECT * FROM revisions WHERE status != 'building' ORDER BY revision DESC LIMIT 1",
+        )?;
+        let row = from_rows::<Self>(stmt.query([])?).next().transpose()?;
+
+        Ok(row)
+    }
+
+    /// Allocate the next revision number and log a build of it as started.
+    ///
+    /// Numbers are never handed out twice, even to builders in other processes or to builds that
+    /// failed.
+    pub fn start(db: &mut Connection, changes: usize) -> Result<usize> {
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let last: Option<usize> = tx.query_row(
+            "SELECT MAX(revision) FROM (
+                SELECT revision FROM revisions UNION ALL SELECT revision FROM revision_files
+            )",
+            [],
+            |r| r.get(0),
+        )?;
+        let revision = last.map(|r| r + 1).unwrap_or_default();
+        tx.execute(
+            &format!(
+                "INSERT INTO revisions (revision, status, started_at, changes)
+                VALUES (?1, 'building', {}, ?2)",
+                NOW
+            ),
+            params![revision, changes],
+        )?;
+        tx.commit()?;
+
+        Ok(revision)
+    }
+
+    /// Log a build as finished, with `error` if it failed. Builds that left pages out still
+    /// succeed, the pages are listed in `page_errors`.
+    pub fn finish(
+        db: &Connection,
+        revision: usize,
+        metrics: &BuildMetrics,
+        duration_ms: u64,
+        error: Option<&str>,
+    ) -> Result<()> {
+        let status = match error {
+            Some(_) => BuildStatus::Failed,
+            None => BuildStatus::Succeeded,
+        };
+        db.execute(
+            &format!(
+                "UPDATE revisions SET
+                    status = ?2, finished_at = {}, duration_ms = ?3, input_files_ms = ?4,
+                    routes_ms = ?5, stylesheets_ms = ?6, files = ?7, routes = ?8, error = ?9,
+                    page_errors = ?10
+                WHERE revision = ?1",
+                NOW
+            ),
+            params![
+                revision,
+                status.to_string(),
+                duration_ms,
+                metrics.input_files_ms,
+                metrics.routes_ms,
+                metrics.stylesheets_ms,
+                metrics.files,
+                metrics.routes,
+                error,
+                (!metrics.page_errors.is_empty())
+                    .then(|| serde_json::to_string(&metrics.page_errors))
+                    .transpose()?
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Forget a build that turned out to have nothing to do.
+    pub fn discard(db: &Connection, revision: usize) -> Result<()> {
+        db.execute(
+            "DELETE FROM revisions WHERE revision = ?1",
+            params![revision],
+        )?;
+        Ok(())
+    }
+
+    /// Mark builds that have been running for longer than `BUILD_LEASE` as failed, they were
+    /// interrupted by their builder going away. Younger ones may belong to another builder
+    /// sharing the database.
+    pub fn interrupt_unfinished(db: &Connection) -> Result<usize> {
+        let interrupted = db.execute(
+            &format!(
+                "UPDATE revisions SET status = 'failed', finished_at = {}, error = 'Interrupted'
+                WHERE status = 'building'
+                AND started_at < strftime('%Y-%m-%dT%H:%M:%fZ', 'now', ?1)",
+                NOW
+            ),
+            params![format!("-{} seconds", BUILD_LEASE.as_secs())],
+        )?;
+        Ok(interrupted)
+    }
+}
+
+impl Migration for RevisionBuild {
+    fn migrate(db: &Connection) -> Result<()> {
+        log::trace!("Creating RevisionBuild...");
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS revisions (
+            revision INT PRIMARY KEY,
+            status VARCHAR NOT NULL,
+            started_at VARCHAR NOT NULL,
+            finished_at VARCHAR,
+            duration_ms INT,
+            input_files_ms INT,
+            routes_ms INT,
+            stylesheets_ms INT,
+            changes INT NOT NULL,
+            files INT,
+            routes INT,
+            error VARCHAR,
+            page_errors JSON
+        );",
+            [],
+        )?;
+        add_column(db, "revisions", "page_errors", "JSON")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::Value;
+use similar::TextDiff;
+
+use crate::{
+    db::{
+        pages::Page,
+        revision_files::RevisionFile,
+        revision_routes::{RevisionRoute, RevisionRouteKind},
+    },
+    Result,
+};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl ChangeKind {
+    pub fn symbol(&self) -> char {
+        match self {
+            ChangeKind::Added => '+',
+            ChangeKind::Removed => '-',
+            ChangeKind::Modified => '~',
+        }
+    }
+
+    fn between<T: PartialEq>(old: Option<&T>, new: Option<&T>) -> Option<Self> {
+        match (old, new) {
+            (None, Some(_)) => Some(ChangeKind::Added),
+            (Some(_), None) => Some(ChangeKind::Removed),
+            (Some(old), Some(new)) if old != new => Some(ChangeKind::Modified),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct FileChange {
+    pub path: String,
+    pub kind: ChangeKind,
+    pub old_hash: Option<String>,
+    pub new_hash: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RouteChange {
+    pub route_path: String,
+    pub kind: ChangeKind,
+    pub route_kind: RevisionRouteKind,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PageChange {
+    pub path: String,
+    pub kind: ChangeKind,
+    pub fields: Vec<FieldChange>,
+    /// Unified diff of the markdown body, `None` if it didn't change.
+    pub body: Option<String>,
+}
+
+/// Everything that changed between two revisions.
+#[derive(Serialize, Debug)]
+pub struct RevisionDiff {
+    pub from: usize,
+    pub to: usize,
+    pub files: Vec<FileChange>,
+    pub routes: Vec<RouteChange>,
+    pub pages: Vec<PageChange>,
+}
+
+impl RevisionDiff {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.routes.is_empty() && self.pages.is_empty()
+    }
+}
+
+/// Compare revision `from` to revision `to`.
+pub fn diff_revisions(db: &Connection, from: usize, to: usize) -> Result<RevisionDiff> {
+    Ok(RevisionDiff {
+        from,
+        to,
+        files: diff_files(db, from, to)?,
+        routes: diff_routes(db, from, to)?,
+        pages: diff_pages(db, from, to)?,
+    })
+}
+
+fn diff_files(db: &Connection, from: usize, to: usize) -> Result<Vec<FileChange>> {
+    let by_path = |rev| -> Result<BTreeMap<String, String>> {
+        Ok(RevisionFile::for_revision(db, rev)?
+            .into_iter()
+            .map(|(hash, path)| (path, hash))
+            .collect())
+    };
+    let old = by_path(from)?;
+    let new = by_path(to)?;
+
+    let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| {
+            let (old_hash, new_hash) = (old.get(path), new.get(path));
+            ChangeKind::between(old_hash, new_hash).map(|kind| FileChange {
+                path: path.clone(),
+                kind,
+                old_hash: old_hash.cloned(),
+                new_hash: new_hash.cloned(),
+            })
+        })
+        .collect())
+}
+
+fn diff_routes(db: &Connection, from: usize, to: usize) -> Result<Vec<RouteChange>> {
+    // What a route serves, stylesheets don't have a hash so their compiled output is used.
+    #[derive(PartialEq)]
+    struct Target {
+        kind: u32,
+        hash: String,
+        path: String,
+        template: Option<String>,
+    }
+
+    let by_route = |rev| -> Result<BTreeMap<String, (RevisionRouteKind, Target)>> {
+        let mut stylesheet =
+            db.prepare("SELECT data FROM revision_stylesheets WHERE revision = ?1 AND name = ?2")?;
+        let mut routes = BTreeMap::new();
+        for route in RevisionRoute::for_revision(db, rev)? {
+            let hash = match route.kind {
+                RevisionRouteKind::Stylesheet => {
+                    let data: String =
+                        stylesheet.query_row(params![rev, route.path], |r| r.get(0))?;
+                    format!("{:016x}", seahash::hash(data.as_bytes()))
+                }
+                _ => route.hash,
+            };
+            let target = Target {
+                kind: route.kind as u32,
+                hash,
+                path: route.path,
+                template: route.template,
+            };
+            routes.insert(route.route_path, (route.kind, target));
+        }
+        Ok(routes)
+    };
+    let old = by_route(from)?;
+    let new = by_route(to)?;
+
+    let route_paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    Ok(route_paths
+        .into_iter()
+        .filter_map(|route_path| {
+            let (old, new) = (old.get(route_path), new.get(route_path));
+            let kind = ChangeKind::between(old.map(|o| &o.1), new.map(|n| &n.1))?;
+            Some(RouteChange {
+                route_path: route_path.clone(),
+                kind,
+                route_kind: new.or(old).unwrap().0,
+            })
+        })
+        .collect())
+}
+
+fn diff_pages(db: &Connection, from: usize, to: usize) -> Result<Vec<PageChange>> {
+    let by_path = |rev| -> Result<BTreeMap<String, Page>> {
+        Ok(Page::for_revision(db, rev)?
+            .into_iter()
+            .map(|p| (p.path.clone(), p))
+            .collect())
+    };
+    let old = by_path(from)?;
+    let new = by_path(to)?;
+
+    let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    let mut changes = vec![];
+    for path in paths {
+        let (old, new) = (old.get(path), new.get(path));
+        let kind = match ChangeKind::between(old.map(|p| &p.hash), new.map(|p| &p.hash)) {
+            Some(kind) => kind,
+            None => continue,
+        };
+
+        let old_tags = old.map(|p| page_tags(db, p)).transpose()?;
+        let new_tags = new.map(|p| page_tags(db, p)).transpose()?;
+        let mut fields = vec![];
+        let mut field = |field, old: Option<String>, new: Option<String>| {
+            if old != new {
+                fields.push(FieldChange { field, old, new });
+            }
+        };
+        field(
+            "title",
+            old.map(|p| p.title.clone()),
+            new.map(|p| p.title.clone()),
+        );
+        field(
+            "date",
+            old.map(|p| p.date.clone()),
+            new.map(|p| p.date.clone()),
+        );
+        field("tags", old_tags, new_tags);
+        field(
+            "template",
+            old.and_then(|p| p.template.clone()),
+            new.and_then(|p| p.template.clone()),
+        );
+        field(
+            "draft",
+            old.map(|p| p.draft.to_string()),
+            new.map(|p| p.draft.to_string()),
+        );
+        field(
+            "extra",
+            old.map(|p| Value::Object(p.extra.clone()).to_string()),
+            new.map(|p| Value::Object(p.extra.clone()).to_string()),
+        );
+        field(
+            "route_path",
+            old.map(|p| p.route_path.clone()),
+            new.map(|p| p.route_path.clone()),
+        );
+
+        let old_body = old
+            .map(|p| page_body(db, p))
+            .transpose()?
+            .unwrap_or_default();
+        let new_body = new
+            .map(|p| page_body(db, p))
+            .transpose()?
+            .unwrap_or_default();
+        let body = (old_body != new_body).then(|| {
+            TextDiff::from_lines(&old_body, &new_body)
+                .unified_diff()
+                .header(&format!("a/{}", path), &format!("b/{}", path))
+                .to_string()
+        });
+
+        changes.push(PageChange {
+            path: path.clone(),
+            kind,
+            fields,
+            body,
+        });
+    }
+    Ok(changes)
+}
+
+fn page_tags(db: &Connection, page: &Page) -> Result<String> {
+    let mut stmt =
+        db.prepare("SELECT tag FROM page_tags WHERE hash = ?1 AND path = ?2 ORDER BY tag")?;
+    let tags = stmt
+        .query_map(params![page.hash, page.path], |r| r.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(tags.join(", "))
+}
+
+/// The markdown of a page, without its front matter.
+fn page_body(db: &Connection, page: &Page) -> Result<String> {
+    let contents: Vec<u8> = db.query_row(
+        "SELECT contents FROM input_files WHERE hash = ?1 AND path = ?2",
+        params![page.hash, page.path],
+        |r| r.get(0),
+    )?;
+    Ok(String::from_utf8_lossy(&contents[page.content_offset.min(contents.len())..]).into_owned())
+}
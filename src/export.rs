@@ -0,0 +1,117 @@
+use std::path::{Path, PathBuf};
+
+use eyre::eyre;
+use liquid::Parser;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+
+use crate::{
+    config::Config,
+    db::{
+        active_revision::ActiveRevision,
+        revision_routes::{RevisionRoute, RevisionRouteKind},
+    },
+    render::render_route,
+    Result,
+};
+
+/// What an export wrote out.
+#[derive(Debug)]
+pub struct ExportSummary {
+    pub revision: usize,
+    pub files: usize,
+    pub skipped: usize,
+}
+
+/// Render every route of `revision` (or the one being served) into `out_dir`.
+///
+/// Pages are written as `<route>/index.html`, static assets and stylesheets
+/// keep their route path, so the result can be served by any static file host.
+/// Existing files in `out_dir` are overwritten but never removed.
+pub fn export_revision(
+    config: &Config,
+    pool: &Pool<SqliteConnectionManager>,
+    templater: &Parser,
+    revision: Option<usize>,
+    out_dir: &Path,
+) -> Result<ExportSummary> {
+    let conn = pool.get()?;
+    let revision = match revision {
+        Some(r) => r,
+        None => ActiveRevision::resolve(&conn)?
+            .ok_or_else(|| eyre!("There are no revisions to export yet"))?,
+    };
+
+    let routes = RevisionRoute::for_revision(&conn, revision)?;
+    if routes.is_empty() {
+        return Err(eyre!("Revision {} has no routes", revision));
+    }
+
+    log::info!(
+        "Exporting revision {} to {}...",
+        revision,
+        out_dir.display()
+    );
+    std::fs::create_dir_all(out_dir)?;
+
+    let mut summary = ExportSummary {
+        revision,
+        files: 0,
+        skipped: 0,
+    };
+    for route in &routes {
+        let dest = match output_path(out_dir, route) {
+            Some(dest) => dest,
+            None => {
+                log::warn!("Skipping {:?} route {:?}", route.kind, route.route_path);
+                summary.skipped += 1;
+                continue;
+            }
+        };
+
+        log::debug!("Writing {:?} to {}", route.route_path, dest.display());
+        let rendered = render_route(config, &conn, templater, route, "/")
+            .map_err(|e| eyre!(e).wrap_err(format!("while rendering {:?}", route.route_path)))?;
+
+        std::fs::create_dir_all(dest.parent().unwrap())?;
+        std::fs::write(&dest, &rendered.body)?;
+        summary.files += 1;
+    }
+
+    std::fs::write(out_dir.join("404.html"), not_found_page())?;
+
+    log::info!(
+        "Exported revision {}: {} files written, {} skipped",
+        summary.revision,
+        summary.files,
+        summary.skipped
+    );
+    Ok(summary)
+}
+
+/// The server's not found page, without the live reload script an export has no use for.
+fn not_found_page() -> String {
+    include_str!("NotFound.liquid")
+        .lines()
+        .filter(|line| !line.contains("/js/reload.js"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Where a route ends up on disk, or `None` if it has no static representation.
+fn output_path(out_dir: &Path, route: &RevisionRoute) -> Option<PathBuf> {
+    let mut dest = out_dir.to_path_buf();
+    for tok in route.route_path.split('/') {
+        match tok {
+            "" | "." => {}
+            ".." => return None,
+            tok => dest.push(tok),
+        }
+    }
+
+    match route.kind {
+        RevisionRouteKind::Page => Some(dest.join("index.html")),
+        RevisionRouteKind::StaticAsset | RevisionRouteKind::Stylesheet => Some(dest),
+        _ => None,
+    }
+}
@@ -1,110 +1,392 @@
-use eyre::Report;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
-use crate::Result;
+use crate::date::Date;
 
-enum State {
-    SearchForStart,
-    ReadingMarker { count: usize, end: bool },
-    ReadingFrontMatter { buf: String, line_start: bool },
-    SkipNewLine { end: bool },
+/// Front matter that couldn't be parsed, with where in the file it went wrong.
+#[derive(Debug, thiserror::Error)]
+#[error("{path}:{line}:{column}: {kind}")]
+pub struct Error {
+    pub path: String,
+    /// 1-based.
+    pub line: usize,
+    /// 1-based, in characters.
+    pub column: usize,
+    pub kind: ErrorKind,
 }
 
 #[derive(Debug, thiserror::Error)]
-pub enum Error {
-    #[error("EOF while parsing frontmatter")]
-    Eof,
+pub enum ErrorKind {
+    #[error("Front matter is never closed, expected `{0}{0}{0}` on a line of its own")]
+    Unclosed(char),
+    #[error("Expected a newline after the front matter")]
+    TrailingCharacters,
+    #[error("Invalid YAML front matter: {0}")]
+    Yaml(String),
+    #[error("Invalid TOML front matter: {0}")]
+    Toml(String),
+    #[error("Invalid JSON front matter: {0}")]
+    Json(String),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Every key is optional, the title and date of a page are inferred when missing.
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct FrontMatter {
-    pub title: String,
-    pub date: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub date: Option<Date>,
     #[serde(default)]
     pub tags: Vec<String>,
     #[serde(default)]
     pub template: Option<String>,
     #[serde(default)]
     pub draft: bool,
+    /// Every other key, kept for templates and queries.
+    #[serde(skip)]
+    pub extra: Map<String, Value>,
+}
+
+/// Keys with a field of their own, left out of `extra`.
+const KNOWN_KEYS: &[&str] = &["title", "date", "tags", "template", "draft"];
+
+/// Turn front matter parsed as a map into the keys for `extra`.
+fn extra(mut all: Map<String, Value>) -> Map<String, Value> {
+    for key in KNOWN_KEYS {
+        all.remove(*key);
+    }
+    all.into_iter()
+        .map(|(k, v)| (k, toml_dates_as_strings(v)))
+        .collect()
+}
+
+/// TOML dates deserialize as a map with a single private key, keep them as written instead.
+fn toml_dates_as_strings(value: Value) -> Value {
+    const TOML_DATETIME: &str = "$__toml_private_datetime";
+    match value {
+        Value::Object(mut map) if map.len() == 1 && map.contains_key(TOML_DATETIME) => {
+            map.remove(TOML_DATETIME).unwrap()
+        }
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(k, v)| (k, toml_dates_as_strings(v)))
+                .collect(),
+        ),
+        Value::Array(values) => {
+            Value::Array(values.into_iter().map(toml_dates_as_strings).collect())
+        }
+        value => value,
+    }
 }
 
 impl FrontMatter {
-    pub fn parse(name: &str, input: &str) -> Result<(Self, usize)> {
-        let mut state = State::SearchForStart;
-
-        let mut payload = None;
-        let offset;
-
-        let mut chars = input.char_indices();
-        'parse: loop {
-            let (idx, ch) = match chars.next() {
-                Some(x) => x,
-                None => return Err(Error::Eof.into()),
-            };
-            match &mut state {
-                State::SearchForStart => match ch {
-                    '-' => {
-                        state = State::ReadingMarker {
-                            count: 1,
-                            end: false,
-                        };
-                    }
-                    '\n' | '\t' | ' ' => {}
-                    _ => {
-                        panic!("Start of frontmatter not found");
-                    }
-                },
-                State::ReadingMarker { count, end } => match ch {
-                    '-' => {
-                        *count += 1;
-                        if *count == 3 {
-                            state = State::SkipNewLine { end: *end };
-                        }
-                    }
-                    _ => {
-                        panic!("Malformed frontmatter marker");
-                    }
-                },
-                State::SkipNewLine { end } => match ch {
-                    '\n' => {
-                        if *end {
-                            offset = idx + 1;
-                            break 'parse;
-                        } else {
-                            state = State::ReadingFrontMatter {
-                                buf: String::new(),
-                                line_start: true,
-                            }
-                        }
-                    }
-                    _ => panic!("Expected newline, got {:?}", ch),
-                },
-                State::ReadingFrontMatter { buf, line_start } => match ch {
-                    '-' if *line_start => {
-                        let mut state_temp = State::ReadingMarker {
-                            count: 1,
-                            end: true,
-                        };
-                        std::mem::swap(&mut state, &mut state_temp);
-                        if let State::ReadingFrontMatter { buf, .. } = state_temp {
-                            payload = Some(buf);
-                        } else {
-                            unreachable!();
-                        }
-                    }
-                    ch => {
-                        buf.push(ch);
-                        *line_start = ch == '\n';
-                    }
-                },
+    /// Parse the front matter at the start of a page, returning it and the offset the body
+    /// starts at.
+    ///
+    /// Front matter is YAML between `---` lines, TOML between `+++` lines, or a JSON object with a
+    /// quoted key right after its `{`. A page starting with anything else, like Liquid's `{{`, has
+    /// none, it gets the default front matter and its body starts at 0.
+    pub fn parse(path: &str, input: &str) -> Result<(Self, usize), Error> {
+        let error = |at: usize, kind| {
+            let (line, column) = position(input, at);
+            Error {
+                path: path.to_string(),
+                line,
+                column,
+                kind,
+            }
+        };
+
+        let start = input.len() - input.trim_start_matches(['\n', '\r', '\t', ' ']).len();
+        if is_json_object(&input[start..]) {
+            return Self::parse_json(path, input, start);
+        }
+        // Anything else, like a list, a `----` thematic break or Liquid, is the start of the body.
+        let (first_line, mut next) = line_at(input, start);
+        let marker = match first_line.trim_end() {
+            "---" => '-',
+            "+++" => '+',
+            _ => return Ok((Self::default(), 0)),
+        };
+        let fence = marker.to_string().repeat(3);
+
+        let payload_start = next;
+        let (payload, offset) = loop {
+            if next >= input.len() {
+                return Err(error(start, ErrorKind::Unclosed(marker)));
+            }
+            let (line, after) = line_at(input, next);
+            if line.trim_end() == fence {
+                break (&input[payload_start..next], after);
+            }
+            next = after;
+        };
+        if payload.trim().is_empty() {
+            return Ok((Self::default(), offset));
+        }
+
+        // Positions in the payload are relative to it, the payload starts on a line of its own.
+        let payload_line = position(input, payload_start).0;
+        let remap = |line: usize, column: usize, message: String| {
+            let message = strip_position(message, line, column);
+            (payload_line + line - 1, column, message)
+        };
+        // Parsed twice, into the known fields and into a map for everything else.
+        let result = match marker {
+            '+' => toml::from_str::<Self>(payload)
+                .and_then(|fm| Ok((fm, toml::from_str::<Map<String, Value>>(payload)?)))
+                .map_err(|e| {
+                    let (line, column) = e.line_col().map_or((1, 1), |(l, c)| (l + 1, c + 1));
+                    let (line, column, message) = remap(line, column, e.to_string());
+                    (line, column, ErrorKind::Toml(message))
+                }),
+            _ => serde_yaml::from_str::<Self>(payload)
+                .and_then(|fm| Ok((fm, serde_yaml::from_str::<Map<String, Value>>(payload)?)))
+                .map_err(|e| {
+                    let (line, column) = e.location().map_or((1, 1), |l| (l.line(), l.column()));
+                    let (line, column, message) = remap(line, column, e.to_string());
+                    (line, column, ErrorKind::Yaml(message))
+                }),
+        };
+
+        match result {
+            Ok((mut fm, all)) => {
+                fm.extra = extra(all);
+                Ok((fm, offset))
             }
+            Err((line, column, kind)) => Err(Error {
+                path: path.to_string(),
+                line,
+                column,
+                kind,
+            }),
         }
+    }
 
-        let payload = payload.unwrap();
+    /// Parse a JSON object starting at `start`, the body starts on the line after it.
+    fn parse_json(path: &str, input: &str, start: usize) -> Result<(Self, usize), Error> {
+        let (start_line, start_column) = position(input, start);
+        let mut objects = serde_json::Deserializer::from_str(&input[start..]).into_iter::<Self>();
+        let mut fm = match objects.next() {
+            Some(Ok(fm)) => fm,
+            Some(Err(e)) => {
+                let message = strip_position(e.to_string(), e.line(), e.column());
+                // Positions are relative to the start of the object.
+                let (line, column) = (e.line().max(1), e.column().max(1));
+                return Err(Error {
+                    path: path.to_string(),
+                    line: start_line + line - 1,
+                    column: if line == 1 {
+                        start_column + column - 1
+                    } else {
+                        column
+                    },
+                    kind: ErrorKind::Json(message),
+                });
+            }
+            None => unreachable!("the input starts with `{{`"),
+        };
 
-        let fm: Self = serde_yaml::from_str(&payload)
-            .map_err(|e| Report::from(e).wrap_err(format!("while parsing {:?}", name)))?;
+        let end = start + objects.byte_offset();
+        fm.extra =
+            extra(serde_json::from_str(&input[start..end]).expect("already parsed as an object"));
+        let (rest, offset) = line_at(input, end);
+        if !rest.trim().is_empty() {
+            let (line, column) = position(input, end);
+            return Err(Error {
+                path: path.to_string(),
+                line,
+                column,
+                kind: ErrorKind::TrailingCharacters,
+            });
+        }
 
         Ok((fm, offset))
     }
 }
+
+/// Whether `input` starts with a JSON object rather than a brace of the body, like Liquid's `{{`
+/// and `{%`: a `{` followed by a quoted key.
+fn is_json_object(input: &str) -> bool {
+    input
+        .strip_prefix('{')
+        .is_some_and(|rest| rest.trim_start().starts_with('"'))
+}
+
+/// The line starting at `start` without its newline, and where the next line starts.
+fn line_at(input: &str, start: usize) -> (&str, usize) {
+    match input[start..].find('\n') {
+        Some(newline) => (&input[start..start + newline], start + newline + 1),
+        None => (&input[start..], input.len()),
+    }
+}
+
+/// The 1-based line and column of a byte offset.
+fn position(input: &str, at: usize) -> (usize, usize) {
+    let before = &input[..at];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Drop the position YAML, TOML and JSON errors end their messages with, as it is relative to the
+/// front matter rather than the file.
+fn strip_position(message: String, line: usize, column: usize) -> String {
+    let suffix = format!(" at line {} column {}", line, column);
+    match message.strip_suffix(&suffix) {
+        Some(message) => message.to_string(),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorKind, FrontMatter};
+
+    fn parse(input: &str) -> (FrontMatter, &str) {
+        let (fm, offset) = FrontMatter::parse("page.md", input).unwrap();
+        (fm, &input[offset..])
+    }
+
+    fn error(input: &str) -> (usize, usize, ErrorKind) {
+        let e = FrontMatter::parse("page.md", input).unwrap_err();
+        assert_eq!(e.path, "page.md");
+        (e.line, e.column, e.kind)
+    }
+
+    #[test]
+    fn parses_yaml() {
+        let (fm, body) = parse("---\ntitle: Hello\ndate: 2022-05-03\nweight: 2\n---\nBody\n");
+        assert_eq!(fm.title.as_deref(), Some("Hello"));
+        assert_eq!(fm.date.unwrap().original, "2022-05-03");
+        assert_eq!(fm.extra["weight"], 2);
+        assert_eq!(body, "Body\n");
+    }
+
+    #[test]
+    fn parses_toml() {
+        let (fm, body) = parse("+++\ntitle = \"Hello\"\ndate = 2022-05-03\n+++\nBody\n");
+        assert_eq!(fm.title.as_deref(), Some("Hello"));
+        assert_eq!(fm.date.unwrap().original, "2022-05-03");
+        assert_eq!(body, "Body\n");
+    }
+
+    #[test]
+    fn parses_json() {
+        let (fm, body) = parse("{\n  \"title\": \"Hello\",\n  \"date\": \"2022-05-03\"\n}\nBody\n");
+        assert_eq!(fm.title.as_deref(), Some("Hello"));
+        assert_eq!(body, "Body\n");
+    }
+
+    #[test]
+    fn skips_leading_blank_lines() {
+        let (fm, body) = parse("\n  \n---\ntitle: Hello\n---\nBody\n");
+        assert_eq!(fm.title.as_deref(), Some("Hello"));
+        assert_eq!(body, "Body\n");
+    }
+
+    #[test]
+    fn accepts_crlf() {
+        let (fm, body) = parse("---\r\ntitle: Hello\r\n---\r\nBody\r\n");
+        assert_eq!(fm.title.as_deref(), Some("Hello"));
+        assert_eq!(body, "Body\r\n");
+
+        let (fm, body) = parse("+++\r\ntitle = \"Hello\"\r\n+++\r\nBody\r\n");
+        assert_eq!(fm.title.as_deref(), Some("Hello"));
+        assert_eq!(body, "Body\r\n");
+    }
+
+    #[test]
+    fn yaml_errors_point_into_the_file() {
+        let (line, column, kind) = error("\n---\ntitle: Hello\ndate: May 3\n---\n");
+        assert_eq!((line, column), (4, 7));
+        assert!(matches!(kind, ErrorKind::Yaml(m) if m.contains("invalid date \"May 3\"")));
+
+        let (line, column, kind) = error("---\ntitle: [Hello\n---\n");
+        assert_eq!((line, column), (3, 1));
+        assert!(matches!(kind, ErrorKind::Yaml(m) if !m.contains(" at line ")));
+    }
+
+    #[test]
+    fn toml_errors_point_into_the_file() {
+        let (line, column, kind) = error("+++\ntitle = \"Hello\"\ndate = \n+++\n");
+        assert_eq!((line, column), (3, 8));
+        assert!(matches!(kind, ErrorKind::Toml(m) if !m.contains(" at line ")));
+    }
+
+    #[test]
+    fn json_errors_point_into_the_file() {
+        let (line, column, kind) = error("\n{\n  \"title\": \"Hello\",\n  \"date\": ,\n}\n");
+        assert_eq!((line, column), (4, 11));
+        assert!(matches!(kind, ErrorKind::Json(m) if !m.contains(" at line ")));
+
+        // Columns on the first line of the object count from the start of the line.
+        let (line, column, _) = error("  {\"title\": }\n");
+        assert_eq!((line, column), (1, 13));
+    }
+
+    #[test]
+    fn json_needs_a_newline_after_it() {
+        let (line, column, kind) = error("{\"title\": \"Hello\"} Body\n");
+        assert_eq!((line, column), (1, 19));
+        assert!(matches!(kind, ErrorKind::TrailingCharacters));
+    }
+
+    #[test]
+    fn front_matter_is_optional() {
+        for input in ["# Hello\n\nBody\n", "- a\n- b\n", "+ a\n", ""] {
+            let (fm, body) = parse(input);
+            assert!(fm.title.is_none() && fm.date.is_none() && fm.template.is_none());
+            assert_eq!(body, input);
+        }
+
+        let (fm, body) = parse("---\n---\nBody\n");
+        assert!(fm.title.is_none());
+        assert_eq!(body, "Body\n");
+    }
+
+    #[test]
+    fn unclosed_fences() {
+        let (line, column, kind) = error("\n---\ntitle: Hello\nBody\n");
+        assert_eq!((line, column), (2, 1));
+        assert!(matches!(kind, ErrorKind::Unclosed('-')));
+
+        let (_, _, kind) = error("+++\ntitle = \"Hello\"\n");
+        assert!(matches!(kind, ErrorKind::Unclosed('+')));
+    }
+
+    #[test]
+    fn fences_are_exactly_three_markers() {
+        for input in [
+            "----\nBody\n---\n",
+            "+++++\nBody\n",
+            "--- title: Hello\n---\n",
+        ] {
+            let (fm, body) = parse(input);
+            assert!(fm.title.is_none());
+            assert_eq!(body, input);
+        }
+
+        let (fm, body) = parse("---   \ntitle: Hello\n---\nBody\n");
+        assert_eq!(fm.title.as_deref(), Some("Hello"));
+        assert_eq!(body, "Body\n");
+    }
+
+    #[test]
+    fn braces_of_the_body_are_not_json() {
+        for input in [
+            "{{ page.title }}\n\nBody\n",
+            "{% if page.draft %}Draft{% endif %}\n",
+            "{ not json }\n",
+            "{}\n",
+        ] {
+            let (fm, body) = parse(input);
+            assert!(fm.title.is_none());
+            assert_eq!(body, input);
+        }
+
+        let (fm, body) = parse("{ \"title\": \"Hello\" }\n{{ page.title }}\n");
+        assert_eq!(fm.title.as_deref(), Some("Hello"));
+        assert_eq!(body, "{{ page.title }}\n");
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    time::{Duration, SystemTime},
+};
+
+use eyre::eyre;
+use fallible_iterator::FallibleIterator;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection};
+
+use crate::{
+    config::Config,
+    db::{active_revision::ActiveRevision, revision_labels::RevisionLabel},
+    Result,
+};
+
+/// Cache blobs younger than this are left alone, they may belong to a revision that is still
+/// being built.
+const CACHE_GRACE_PERIOD: Duration = Duration::from_secs(15 * 60);
+
+/// Which revisions survive a collection.
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    /// How many of the newest revisions to keep.
+    pub keep_last: usize,
+}
+
+/// What a collection removed (or would remove, for a dry run).
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub kept_revisions: Vec<usize>,
+    pub removed_revisions: Vec<usize>,
+    pub revision_files: usize,
+    pub revision_routes: usize,
+    pub revision_stylesheets: usize,
+    pub revision_builds: usize,
+    pub input_files: usize,
+    pub input_file_bytes: u64,
+    pub pages: usize,
+    pub page_tags: usize,
+    pub cache_blobs: usize,
+    pub cache_bytes: u64,
+}
+
+/// Remove every revision not kept by `retention`, along with the files and cache blobs that no
+/// surviving revision references.
+///
+/// The newest `keep_last` revisions, labelled revisions, the active revision and the newest
+/// revision are always kept, `keep_last` has to be at least 1.
+/// With `dry_run` nothing is deleted, but the report is the same.
+pub fn collect_garbage(
+    config: &Config,
+    pool: &Pool<SqliteConnectionManager>,
+    retention: Retention,
+    dry_run: bool,
+) -> Result<GcReport> {
+    if retention.keep_last == 0 {
+        return Err(eyre!(
+            "At least one revision has to be kept, keep_revisions can't be 0"
+        ));
+    }
+
+    let started = SystemTime::now();
+    let mut conn = pool.get()?;
+    let tx = conn.transaction()?;
+
+    let all = all_revisions(&tx)?;
+    let mut keep: BTreeSet<usize> = all
+        .iter()
+        .rev()
+        .take(retention.keep_last)
+        .copied()
+        .collect();
+    keep.extend(RevisionLabel::all(&tx)?.into_iter().map(|l| l.revision));
+    keep.extend(ActiveRevision::resolve(&tx)?);
+    // The next build is based on the newest revision, even when an older one is pinned.
+    keep.extend(all.last());
+
+    let mut report = GcReport {
+        kept_revisions: all.iter().filter(|r| keep.contains(r)).copied().collect(),
+        removed_revisions: all.iter().filter(|r| !keep.contains(r)).copied().collect(),
+        ..Default::default()
+    };
+
+    log::info!(
+        "Keeping revisions {:?}, removing {:?}",
+        report.kept_revisions,
+        report.removed_revisions
+    );
+
+    {
+        let remove_revision = |table: &str| -> Result<usize> {
+            let mut stmt = tx.prepare(&format!("DELETE FROM {} WHERE revision = ?1", table))?;
+            let mut removed = 0;
+            for revision in &report.removed_revisions {
+                removed += stmt.execute(params![revision])?;
+            }
+            Ok(removed)
+        };
+        report.revision_files = remove_revision("revision_files")?;
+        report.revision_routes = remove_revision("revision_routes")?;
+        report.revision_stylesheets = remove_revision("revision_stylesheets")?;
+        report.revision_builds = remove_revision("revisions")?;
+    }
+    // Failed builds left no revision behind, their log goes once it is older than every kept
+    // revision.
+    if let Some(oldest_kept) = keep.iter().next() {
+        report.revision_builds += tx.execute(
+            "DELETE FROM revisions WHERE status = 'failed' AND revision < ?1",
+            params![oldest_kept],
+        )?;
+    }
+
+    let unreferenced = |table: &str| {
+        format!(
+            "
+            FROM {table}
+            WHERE NOT EXISTS (
+                SELECT 1 FROM revision_files
+                WHERE revision_files.hash = {table}.hash AND revision_files.path = {table}.path
+            )",
+            table = table
+        )
+    };
+    report.page_tags = tx.execute(&format!("DELETE {}", unreferenced("page_tags")), [])?;
+    report.pages = tx.execute(&format!("DELETE {}", unreferenced("pages")), [])?;
+    report.input_file_bytes = tx.query_row(
+        &format!(
+            "SELECT COALESCE(SUM(size), 0) {}",
+            unreferenced("input_files")
+        ),
+        [],
+        |r| r.get(0),
+    )?;
+    report.input_files = tx.execute(&format!("DELETE {}", unreferenced("input_files")), [])?;
+
+    let referenced_blobs: HashSet<String> = tx
+        .prepare("SELECT DISTINCT hash FROM input_files WHERE NOT inline")?
+        .query([])?
+        .map(|r| r.get(0))
+        .collect()?;
+
+    if dry_run {
+        tx.rollback()?;
+    } else {
+        tx.commit()?;
+    }
+
+    let cutoff = started - CACHE_GRACE_PERIOD;
+    if config.cache_dir().exists() {
+        for entry in std::fs::read_dir(config.cache_dir())? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            // Only blobs are named after their hash, skip anything else in there.
+            if !metadata.is_file()
+                || name.len() != 16
+                || !name.chars().all(|c| c.is_ascii_hexdigit())
+                || referenced_blobs.contains(name.as_ref())
+                || metadata.modified()? > cutoff
+            {
+                continue;
+            }
+
+            if !dry_run {
+                std::fs::remove_file(entry.path())?;
+            }
+            report.cache_blobs += 1;
+            report.cache_bytes += metadata.len();
+        }
+    }
+
+    Ok(report)
+}
+
+fn all_revisions(db: &Connection) -> Result<Vec<usize>> {
+    let revisions = db
+        .prepare(
+            "
+            SELECT revision FROM revision_files
+            UNION SELECT revision FROM revision_routes
+            UNION SELECT revision FROM revision_stylesheets
+            ORDER BY revision
+        ",
+        )?
+        .query([])?
+        .map(|r| r.get(0))
+        .collect()?;
+    Ok(revisions)
+}
@@ -1,21 +1,21 @@
-use std::sync::Arc;
+use std::{
+    io,
+    os::unix::{fs::FileTypeExt, net::UnixStream},
+    sync::Arc,
+};
 
 use liquid::Parser;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::params;
-use serde_rusqlite::from_rows;
-use tide::{http::mime, Error, Response, StatusCode};
-use tokio::{fs::File, io::AsyncReadExt, sync::watch};
+use tide::{http::mime, listener::ConcurrentListener, sse, Error, Request, Response, StatusCode};
+use tide_rustls::TlsListener;
+use tokio::sync::watch;
 
 use crate::{
-    config::Config,
-    db::{
-        input_files::InputFile,
-        pages::Page,
-        revision_routes::{RevisionRoute, RevisionRouteKind},
-        revision_stylesheet::RevisionStylesheet,
-    },
+    config::{Config, OperatingMode, ServerConfig},
+    db::{active_revision::ActiveRevision, revisions::RevisionBuild},
+    reload::{FailedBuild, SiteStatus},
+    render,
 };
 
 #[derive(Clone)]
@@ -23,7 +23,164 @@ pub struct State {
     pub config: Arc<Config>,
     pub db: Pool<SqliteConnectionManager>,
     pub templater: Parser,
-    pub reload_rx: watch::Receiver<usize>,
+    pub reload_rx: watch::Receiver<SiteStatus>,
+}
+
+impl State {
+    /// Whether this server watches the site and builds it. Failed builds are only shown over the
+    /// pages, and the build log only served, then.
+    fn builds_site(&self) -> bool {
+        self.config.operating_mode() == OperatingMode::ReadWrite
+    }
+}
+
+/// Every site being served, picked by the `Host` of each request.
+#[derive(Clone)]
+pub struct Sites(Arc<Vec<(Vec<String>, State)>>);
+
+impl Sites {
+    /// Sites and the hosts they answer to, a site without hosts answers to every host.
+    pub fn new(sites: Vec<(Vec<String>, State)>) -> Self {
+        Self(Arc::new(sites))
+    }
+
+    /// The site answering to `host`, with or without a port.
+    pub fn for_host(&self, host: Option<&str>) -> Option<&State> {
+        let host = host.map(|h| strip_port(h).to_ascii_lowercase());
+        self.0
+            .iter()
+            .find(|(hosts, _)| host.as_ref().is_some_and(|h| hosts.contains(h)))
+            .or_else(|| self.0.iter().find(|(hosts, _)| hosts.is_empty()))
+            .map(|(_, state)| state)
+    }
+
+    fn for_request<T>(&self, req: &Request<T>) -> tide::Result<&State> {
+        self.for_host(req.host())
+            .ok_or_else(|| Error::from_str(StatusCode::NotFound, "Unknown Host"))
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+/// Serve the sites on every address in the server config.
+pub async fn serve(sites: Sites, server: ServerConfig) -> io::Result<()> {
+    let mut app = tide::with_state(sites);
+    app.with(tide::log::LogMiddleware::new());
+
+    app.at("/sse")
+        .get(sse::endpoint(|req: Request<Sites>, sender| async move {
+            let state = req.state().for_request(&req)?;
+            let overlay = state.builds_site();
+            let mut reload_rx = state.reload_rx.clone();
+            let mut shown = reload_rx.borrow_and_update().clone();
+            if overlay {
+                if let Some(build) = &shown.failed_build {
+                    sender
+                        .send("build-failed", &build_failed_event(build)?, None)
+                        .await?;
+                }
+            }
+            loop {
+                reload_rx.changed().await?;
+                let status = reload_rx.borrow_and_update().clone();
+                if status.revision != shown.revision {
+                    sender.send("reload", "reload", None).await?;
+                    return Ok(());
+                }
+                if overlay && status.failed_build != shown.failed_build {
+                    match &status.failed_build {
+                        Some(build) => {
+                            sender
+                                .send("build-failed", &build_failed_event(build)?, None)
+                                .await?
+                        }
+                        None => sender.send("build-succeeded", "", None).await?,
+                    }
+                }
+                shown = status;
+            }
+        }));
+    app.at(OVERLAY_SCRIPT_PATH).get(|_| async {
+        Ok(Response::builder(StatusCode::Ok)
+            .content_type(mime::JAVASCRIPT)
+            .body(include_str!("overlay.js"))
+            .build())
+    });
+    app.at(BUILDS_PATH).get(builds);
+    app.at("/*").get(route_with_catch);
+
+    let mut listener = ConcurrentListener::new();
+    for addr in server.listen() {
+        match server.tls() {
+            Some(tls) => listener.add(
+                TlsListener::build()
+                    .addrs(addr.as_str())
+                    .cert(&tls.cert)
+                    .key(&tls.key),
+            )?,
+            None => listener.add(addr.as_str())?,
+        }
+    }
+    if let Some(socket) = server.unix_socket() {
+        // A socket left behind by a previous run would make binding fail, one a server is still
+        // listening on is left alone.
+        if let Ok(metadata) = std::fs::symlink_metadata(socket) {
+            if metadata.file_type().is_socket() {
+                match UnixStream::connect(socket) {
+                    Ok(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            format!("{} is in use by another server", socket.display()),
+                        ))
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        std::fs::remove_file(socket)?
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+        listener.add(format!("http+unix://{}", socket.display()))?;
+    }
+
+    app.listen(listener).await
+}
+
+/// Where the script showing failed builds over pages is served.
+pub const OVERLAY_SCRIPT_PATH: &str = "/_emphasize/overlay.js";
+
+/// The data of a `build-failed` event, with the error rendered as HTML.
+fn build_failed_event(build: &FailedBuild) -> tide::Result<String> {
+    Ok(serde_json::to_string(&FailedBuild {
+        error: error_html(&build.error)?,
+        ..build.clone()
+    })?)
+}
+
+/// Where the build log of a site is served, as JSON.
+pub const BUILDS_PATH: &str = "/_emphasize/builds";
+
+/// How many builds the build log endpoint lists.
+const BUILDS_LIMIT: usize = 50;
+
+/// The latest builds of the site, newest first. Not found unless the site is built by this server.
+async fn builds(req: Request<Sites>) -> tide::Result<Response> {
+    let state = req.state().for_request(&req)?;
+    if !state.builds_site() {
+        return route_with_catch(req).await;
+    }
+    let builds = RevisionBuild::recent(&*state.db.get()?, BUILDS_LIMIT)
+        .map_err(|e| Error::from_str(StatusCode::InternalServerError, e))?;
+
+    Ok(Response::builder(StatusCode::Ok)
+        .content_type(mime::JSON)
+        .body(serde_json::to_string(&builds)?)
+        .build())
 }
 
 pub fn catch_errors(
@@ -34,195 +191,102 @@ pub fn catch_errors(
     match res {
         Ok(r) => Ok(r),
         Err(e) => match e.status() {
-            StatusCode::NotFound => {
-                let template_str = include_str!("NotFound.liquid");
-
-                Ok(Response::builder(StatusCode::NotFound)
-                    .content_type(mime::HTML)
-                    .body(template_str)
-                    .build())
-            }
+            StatusCode::NotFound => Ok(not_found()),
             status => error(status, e, debug, parser),
         },
     }
 }
 
-pub async fn route_with_catch(req: tide::Request<State>) -> tide::Result<Response> {
-    let parser = &req.state().templater.clone();
-    let debug = req.state().config.debug();
-    let res = route(req).await;
-    catch_errors(res, parser, debug)
+fn not_found() -> Response {
+    let template_str = include_str!("NotFound.liquid");
+
+    Response::builder(StatusCode::NotFound)
+        .content_type(mime::HTML)
+        .body(template_str)
+        .build()
+}
+
+pub async fn route_with_catch(req: tide::Request<Sites>) -> tide::Result<Response> {
+    let sites = req.state().clone();
+    let state = match sites.for_request(&req) {
+        Ok(state) => state,
+        Err(_) => return Ok(not_found()),
+    };
+    let res = route(&req, state).await;
+    catch_errors(res, &state.templater, state.config.debug())
+}
+
+/// Header selecting which revision to render, instead of the active one.
+pub const REVISION_HEADER: &str = "X-Emphasize-Revision";
+
+/// Split a `_rev/<n>/` preview prefix off a request path.
+fn preview_revision(path: &str) -> Option<(usize, &str)> {
+    let rest = path.strip_prefix("_rev/")?;
+    let (revision, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    Some((revision.parse().ok()?, rest))
 }
 
-pub async fn route(req: tide::Request<State>) -> tide::Result<Response> {
+pub async fn route(req: &tide::Request<Sites>, state: &State) -> tide::Result<Response> {
     let path = req.url().path().trim_start_matches('/');
-    let conn = req.state().db.get()?;
-    let templater = &req.state().templater;
-    let config = &req.state().config;
+    let conn = state.db.get()?;
+    let templater = &state.templater;
+    let config = &state.config;
 
     log::debug!("GET {:?}", path);
 
-    let route = {
-        let mut stmt = conn.prepare(
-            "
-            SELECT * FROM revision_routes
-            WHERE revision = (SELECT MAX(revision) FROM revision_routes)
-            AND route_path = ?1
-            ORDER BY revision DESC
-            LIMIT 1;
-        ",
-        )?;
-
-        let mut routes = from_rows::<RevisionRoute>(stmt.query(params![path])?);
-        routes
-            .next()
-            .ok_or_else(|| Error::from_str(StatusCode::NotFound, "Route Not Found"))
-    }??;
-
-    match route.kind {
-        RevisionRouteKind::Page => {
-            let page = {
-                let mut stmt = conn.prepare(
-                    "
-                    SELECT *
-                    FROM pages
-                    WHERE hash = ?1 AND path = ?2
-                ",
-                )?;
-
-                let mut pages = from_rows::<Page>(stmt.query(params![route.hash, route.path])?);
-                pages.next().ok_or_else(|| {
-                    Error::from_str(StatusCode::InternalServerError, "Page Not Found")
-                })
-            }??;
-
-            let template_path = page
-                .template
-                .clone()
-                .ok_or_else(|| Error::from_str(StatusCode::InternalServerError, "No Template"))?;
-
-            let input_file: InputFile = {
-                let mut stmt = conn.prepare(
-                    "
-                    SELECT *
-                    FROM input_files
-                    WHERE hash = ?1 AND path = ?2
-                ",
-                )?;
-
-                let mut files =
-                    from_rows::<InputFile>(stmt.query(params![route.hash, route.path])?);
-                files.next().ok_or_else(|| {
-                    Error::from_str(StatusCode::InternalServerError, "Content Not Found")
-                })
-            }??;
-            // Now get the contents without the frontmatter
-            let content = input_file.contents.into_iter().skip(page.content_offset);
-
-            // Now get the template
-            let template_file: InputFile = {
-                let mut stmt = conn.prepare("
-                    SELECT input_files.*
-                    FROM input_files
-                    INNER JOIN revision_files
-                    ON revision_files.hash = input_files.hash AND revision_files.path = input_files.path
-                    WHERE input_files.path = ?1 AND revision_files.revision = ?2
-                ")?;
-
-                let mut files = from_rows::<InputFile>(stmt.query(params![
-                    format!("templates/{}", template_path),
-                    route.revision
-                ])?);
-                files.next().ok_or_else(|| {
-                    Error::from_str(StatusCode::InternalServerError, "Template sNot Found")
-                })
-            }??;
-
-            // Render it
-            let template = templater.parse(std::str::from_utf8(&template_file.contents)?)?;
-            let html = template.render(&liquid::object!({
-                "source": std::str::from_utf8(&content.collect::<Vec<_>>())?,
-                "page": page
-            }))?;
-
-            let response = Response::builder(200)
-                .body(html)
-                .content_type(mime::HTML)
-                .build();
-
-            Ok(response)
-        }
-        RevisionRouteKind::StaticAsset => {
-            // Get input_file of the route
-            let input_file: InputFile = {
-                let mut stmt = conn.prepare(
-                    "
-                    SELECT *
-                    FROM input_files
-                    WHERE hash = ?1 AND path = ?2
-                ",
-                )?;
-
-                let mut files =
-                    from_rows::<InputFile>(stmt.query(params![route.hash, route.path])?);
-                files.next().ok_or_else(|| {
-                    Error::from_str(StatusCode::InternalServerError, "Content Not Found")
-                })
-            }??;
-
-            let res = Response::builder(200);
-            let res = if input_file.path.ends_with(".png") {
-                res.content_type(mime::PNG)
-            } else if input_file.path.ends_with(".js") {
-                res.content_type(mime::JAVASCRIPT)
-            } else {
-                res.content_type(mime::PLAIN)
-            };
-
-            if input_file.inline {
-                // Just use the contents field
-                Ok(res.body(input_file.contents).build())
-            } else {
-                // Look it up from the cache`
-                let cache_dir = config.cache_dir();
-                let file_dir = cache_dir.join(&input_file.hash);
-                let mut f = File::open(file_dir).await?;
-
-                let mut contents = vec![];
-                f.read_to_end(&mut contents).await?;
-
-                Ok(res.body(contents).build())
-            }
+    // Without previews the header is ignored, a proxy may set it for every request.
+    let header_revision = req
+        .header(REVISION_HEADER)
+        .filter(|_| config.preview())
+        .map(|h| h.as_str().parse::<usize>())
+        .transpose()
+        .map_err(|e| Error::from_str(StatusCode::BadRequest, e))?;
+    let preview = preview_revision(path);
+
+    let (revision, path, base_url) = match (preview, header_revision) {
+        (Some(_), _) if !config.preview() => {
+            return Err(Error::from_str(StatusCode::NotFound, "Previews Disabled"));
         }
-        RevisionRouteKind::Stylesheet => {
-            let stylesheet = {
-                let mut stmt = conn.prepare(
-                    "
-                    SELECT * FROM revision_stylesheets
-                    WHERE revision = ?1 AND name = ?2;
-                ",
-                )?;
-
-                let mut stylesheets = from_rows::<RevisionStylesheet>(
-                    stmt.query(params![route.revision, path.trim_end_matches(".css")])?,
-                );
-                stylesheets
-                    .next()
-                    .ok_or_else(|| Error::from_str(StatusCode::NotFound, "Stylesheet Not Found"))
-            }??;
-
-            let response = Response::builder(200)
-                .body(stylesheet.data)
-                .content_type(mime::CSS)
-                .build();
-
-            Ok(response)
+        (Some((revision, path)), _) => (revision, path, format!("/_rev/{}/", revision)),
+        (None, Some(revision)) => (revision, path, "/".to_string()),
+        (None, None) => {
+            let revision = ActiveRevision::resolve(&conn)
+                .map_err(|e| Error::from_str(StatusCode::InternalServerError, e))?
+                .ok_or_else(|| Error::from_str(StatusCode::NotFound, "Route Not Found"))?;
+            (revision, path, "/".to_string())
         }
-        e => Err(Error::from_str(
-            StatusCode::NotImplemented,
-            format!("Not Implemented for: {:?}", e),
-        )),
-    }
+    };
+
+    // Rendering reads the database and the cache from disk, off the async executor.
+    let rendered = {
+        let (config, templater, path, base_url) = (
+            config.clone(),
+            templater.clone(),
+            path.to_string(),
+            base_url.clone(),
+        );
+        tokio::task::spawn_blocking(move || {
+            let route = render::find_route(&conn, revision, &path)?;
+            render::render_route(&config, &conn, &templater, &route, &base_url)
+        })
+        .await?
+        .map_err(render::Error::into_tide)?
+    };
+    let rendered = if preview.is_some() {
+        rendered.rebase(&base_url)
+    } else {
+        rendered
+    };
+    let rendered = if state.builds_site() {
+        rendered.with_script(OVERLAY_SCRIPT_PATH)
+    } else {
+        rendered
+    };
+
+    Ok(Response::builder(200)
+        .body(rendered.body)
+        .content_type(rendered.mime)
+        .build())
 }
 
 struct DisplayWrap(anyhow::Error);
@@ -241,6 +305,12 @@ impl std::fmt::Debug for DisplayWrap {
     }
 }
 
+/// Escape an error message for HTML, turning its ANSI colors into markup.
+fn error_html(message: &str) -> tide::Result<String> {
+    let escaped = html_escape::encode_text(message);
+    Ok(ansi_to_html::convert(&escaped, false, false)?)
+}
+
 pub fn error(
     status: StatusCode,
     error: Error,
@@ -255,8 +325,7 @@ pub fn error(
     } else {
         format!("{}", wrap_e)
     };
-    let escaped = html_escape::encode_text(&body);
-    let colored = ansi_to_html::convert(&escaped, false, false)?;
+    let colored = error_html(&body)?;
 
     let template_str = include_str!("InternalServerError.liquid");
     let template = parser.parse(template_str)?;
@@ -0,0 +1,316 @@
+use std::{
+    borrow::Cow,
+    path::{Component, Path},
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+/// What the files in a layout directory are used for.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+pub enum Role {
+    /// Markdown pages, other files are served as is alongside them.
+    Content,
+    /// Files served as is.
+    Static,
+    /// Stylesheets, compiled from `style.scss` into `style.css`.
+    Sass,
+    /// Liquid templates pages are rendered with.
+    Templates,
+}
+
+/// A directory of the content directory, and the role its files play.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LayoutDir {
+    /// Relative to the content directory, and inside of it.
+    #[serde(deserialize_with = "relative_dir")]
+    pub dir: String,
+    pub role: Role,
+    /// URL prefix the routes of this directory are served under.
+    #[serde(default = "root_mount")]
+    pub mount: String,
+    /// Template for the pages of this directory that don't name one in their front matter.
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+fn relative_dir<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let dir = String::deserialize(deserializer)?;
+    let path = Path::new(dir.trim_matches('/'));
+    if path.as_os_str().is_empty()
+        || path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(de::Error::custom(format!(
+            "layout dir {:?} has to be a relative path inside content_dir",
+            dir
+        )));
+    }
+    Ok(dir)
+}
+
+fn root_mount() -> String {
+    "/".to_string()
+}
+
+/// Which directories of the content directory are read, and how.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[serde(transparent)]
+pub struct Layout(Vec<LayoutDir>);
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self(
+            [
+                ("content", Role::Content),
+                ("static", Role::Static),
+                ("sass", Role::Sass),
+                ("templates", Role::Templates),
+            ]
+            .into_iter()
+            .map(|(dir, role)| LayoutDir {
+                dir: dir.to_string(),
+                role,
+                mount: root_mount(),
+                template: None,
+            })
+            .collect(),
+        )
+    }
+}
+
+/// A file path split into the layout directory it is in and the rest of the path.
+#[derive(Debug, Clone, Copy)]
+pub struct Resolved<'a> {
+    pub dir: &'a LayoutDir,
+    pub rest: &'a str,
+}
+
+impl Layout {
+    pub fn dirs(&self) -> &[LayoutDir] {
+        &self.0
+    }
+
+    /// The directories with `role`, in the order they were configured.
+    pub fn dirs_with_role(&self, role: Role) -> impl Iterator<Item = &LayoutDir> {
+        self.0.iter().filter(move |d| d.role == role)
+    }
+
+    /// Find the directory a path (relative to the content directory) is in, preferring the
+    /// deepest one if directories are nested.
+    pub fn resolve<'a>(&'a self, path: &'a str) -> Option<Resolved<'a>> {
+        self.0
+            .iter()
+            .filter_map(|dir| {
+                let name = dir.dir.trim_matches('/');
+                let rest = path.strip_prefix(name)?;
+                match rest.strip_prefix('/') {
+                    Some(rest) => Some(Resolved { dir, rest }),
+                    None if rest.is_empty() => Some(Resolved { dir, rest }),
+                    None => None,
+                }
+            })
+            .max_by_key(|r| r.dir.dir.len())
+    }
+
+    pub fn role(&self, path: &str) -> Option<Role> {
+        self.resolve(path).map(|r| r.dir.role)
+    }
+
+    /// Whether a path may come from a theme, only templates, stylesheets and static files do.
+    pub fn is_themeable(&self, path: &str) -> bool {
+        matches!(
+            self.role(path),
+            Some(Role::Static | Role::Sass | Role::Templates)
+        )
+    }
+
+    /// Whether a path is a page, a markdown file in a content directory.
+    pub fn is_page(&self, path: &str) -> bool {
+        self.role(path) == Some(Role::Content) && path.ends_with(".md")
+    }
+
+    /// The template of a page that doesn't name one, the one of its content directory.
+    pub fn page_template<'a>(&'a self, path: &'a str) -> Option<&'a str> {
+        let resolved = self.resolve(path)?;
+        match resolved.dir.role {
+            Role::Content => resolved.dir.template.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The route of a page, `posts/a/index.md` and `posts/a.md` both become `a` if `posts` is a
+    /// content directory mounted at `/`.
+    pub fn page_route_path(&self, path: &str) -> Option<String> {
+        let resolved = self.resolve(path)?;
+        if resolved.dir.role != Role::Content {
+            return None;
+        }
+
+        let rest = resolved.rest;
+        let rest = if rest == "index.md" {
+            ""
+        } else {
+            rest.trim_end_matches("/index.md")
+        };
+        let rest = match rest.rsplit_once('.') {
+            Some((stem, ext)) if !ext.contains('/') && !stem.is_empty() && !stem.ends_with('/') => {
+                stem
+            }
+            _ => rest,
+        };
+        Some(mount(&resolved.dir.mount, rest).into_owned())
+    }
+
+    /// The route a file is served as is under, if any. Templates and stylesheet sources aren't
+    /// served.
+    pub fn asset_route_path(&self, path: &str) -> Option<String> {
+        let resolved = self.resolve(path)?;
+        match resolved.dir.role {
+            Role::Content | Role::Static => {
+                Some(mount(&resolved.dir.mount, resolved.rest).into_owned())
+            }
+            Role::Sass | Role::Templates => None,
+        }
+    }
+
+    /// The route the compiled stylesheet is served under.
+    pub fn stylesheet_route_path(&self) -> String {
+        let dir_mount = self
+            .dirs_with_role(Role::Sass)
+            .next()
+            .map(|d| d.mount.as_str())
+            .unwrap_or("/");
+        mount(dir_mount, "style.css").into_owned()
+    }
+
+    /// Paths a template may be found at, the first one that exists is used.
+    pub fn template_paths<'a>(&'a self, name: &'a str) -> impl Iterator<Item = String> + 'a {
+        self.dirs_with_role(Role::Templates)
+            .map(move |d| format!("{}/{}", d.dir.trim_matches('/'), name))
+    }
+}
+
+/// Join a mount point and a path relative to it into a route path, which has no leading slash.
+fn mount<'a>(mount: &str, rest: &'a str) -> Cow<'a, str> {
+    match mount.trim_matches('/') {
+        "" => Cow::Borrowed(rest),
+        mount if rest.is_empty() => Cow::Owned(mount.to_string()),
+        mount => Cow::Owned(format!("{}/{}", mount, rest)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Layout, Role};
+
+    fn layout() -> Layout {
+        serde_yaml::from_str(
+            "
+            - { dir: content, role: Content }
+            - { dir: content/blog/, role: Content, mount: /posts, template: post.liquid }
+            - { dir: static, role: Static, mount: /assets/ }
+            - { dir: sass, role: Sass }
+            - { dir: templates, role: Templates }
+            - { dir: theme-templates, role: Templates }
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn resolves_to_the_deepest_dir() {
+        let layout = layout();
+        let resolved = layout.resolve("content/blog/a.md").unwrap();
+        assert_eq!(
+            (resolved.dir.dir.as_str(), resolved.rest),
+            ("content/blog/", "a.md")
+        );
+        let resolved = layout.resolve("content/blogs/a.md").unwrap();
+        assert_eq!(
+            (resolved.dir.dir.as_str(), resolved.rest),
+            ("content", "blogs/a.md")
+        );
+        let resolved = layout.resolve("content").unwrap();
+        assert_eq!((resolved.dir.dir.as_str(), resolved.rest), ("content", ""));
+        // Only whole components match.
+        assert!(layout.resolve("contents/a.md").is_none());
+        assert!(layout.resolve("other/a.md").is_none());
+    }
+
+    #[test]
+    fn page_routes() {
+        let layout = layout();
+        let route = |path| layout.page_route_path(path);
+        assert_eq!(route("content/a.md").as_deref(), Some("a"));
+        assert_eq!(route("content/a/index.md").as_deref(), Some("a"));
+        assert_eq!(route("content/index.md").as_deref(), Some(""));
+        assert_eq!(route("content/blog/x.md").as_deref(), Some("posts/x"));
+        assert_eq!(route("content/blog/x/index.md").as_deref(), Some("posts/x"));
+        assert_eq!(route("content/blog/index.md").as_deref(), Some("posts"));
+        assert_eq!(route("static/a.md"), None);
+        assert_eq!(route("other/a.md"), None);
+    }
+
+    #[test]
+    fn page_routes_only_drop_real_extensions() {
+        let layout = layout();
+        let route = |path| layout.page_route_path(path);
+        assert_eq!(route("content/a.b/c.md").as_deref(), Some("a.b/c"));
+        assert_eq!(route("content/a.b/c").as_deref(), Some("a.b/c"));
+        assert_eq!(route("content/posts/.md").as_deref(), Some("posts/.md"));
+        assert_eq!(route("content/.md").as_deref(), Some(".md"));
+        assert_eq!(route("content/a.tar.md").as_deref(), Some("a.tar"));
+    }
+
+    #[test]
+    fn asset_routes_are_mounted() {
+        let layout = layout();
+        let route = |path| layout.asset_route_path(path);
+        assert_eq!(
+            route("static/img/a.png").as_deref(),
+            Some("assets/img/a.png")
+        );
+        assert_eq!(route("content/a.png").as_deref(), Some("a.png"));
+        assert_eq!(route("content/blog/a.png").as_deref(), Some("posts/a.png"));
+        assert_eq!(route("templates/page.liquid"), None);
+        assert_eq!(route("sass/style.scss"), None);
+        assert_eq!(layout.stylesheet_route_path(), "style.css");
+    }
+
+    #[test]
+    fn templates() {
+        let layout = layout();
+        assert_eq!(
+            layout.template_paths("page.liquid").collect::<Vec<_>>(),
+            ["templates/page.liquid", "theme-templates/page.liquid"]
+        );
+        assert_eq!(
+            layout.page_template("content/blog/a.md"),
+            Some("post.liquid")
+        );
+        assert_eq!(layout.page_template("content/a.md"), None);
+        assert_eq!(layout.page_template("static/a.md"), None);
+    }
+
+    #[test]
+    fn roles() {
+        let layout = layout();
+        assert!(layout.is_page("content/blog/a.md"));
+        assert!(!layout.is_page("content/a.png"));
+        assert!(!layout.is_page("static/a.md"));
+        assert!(layout.is_themeable("templates/page.liquid"));
+        assert!(!layout.is_themeable("content/a.md"));
+        assert_eq!(layout.role("sass/_vars.scss"), Some(Role::Sass));
+    }
+
+    #[test]
+    fn dirs_stay_inside_the_content_dir() {
+        for dir in ["..", "a/../b", "", "/", "./a"] {
+            let yaml = format!("[{{ dir: {:?}, role: Static }}]", dir);
+            assert!(serde_yaml::from_str::<Layout>(&yaml).is_err(), "{:?}", dir);
+        }
+        assert!(serde_yaml::from_str::<Layout>("[{ dir: /a/b/, role: Static }]").is_ok());
+    }
+}
@@ -7,55 +7,222 @@
 //! the required configuration.
 //!
 //! You either need to pass in the `CACHE_DIR`, `DB`, `CONTENT_DIR` environment variables, or
-//! create a config file like so:
+//! create a config file (passed with `--config`) like so:
 //! ```yaml
 //! cache_dir: .emphasize/cache/
 //! db: .emphasize/content.db
 //! content_dir: blog
 //! ```
-//! Optionally, you can either pass in any non-empty value with `DEBUG` of set `debug` to true in
-//! your config file to enable backtraces being displayed on internal server errors. (please don't
-//! use this on production, not that you should be using a WIP package there anyway...)
+//! Unknown keys in the config file are rejected, so a typo doesn't silently fall back to a
+//! default.
+//!
+//! Optionally, you can either pass in `DEBUG=true` or set `debug` to true in your config file to
+//! enable backtraces being displayed on internal server errors. (please don't use this on
+//! production, not that you should be using a WIP package there anyway...)
+//!
+//! # Layout
+//! The content directory is read from `content` (pages), `static` (files served as is), `sass`
+//! (compiled from `style.scss` into `/style.css`) and `templates`. Other names, and where their
+//! routes are mounted, can be set with `layout`:
+//! ```yaml
+//! layout:
+//!   - dir: posts
+//!     role: Content
+//!     mount: /blog
+//!   - dir: assets
+//!     role: Static
+//!   - dir: layouts
+//!     role: Templates
+//!   - dir: styles
+//!     role: Sass
+//! ```
+//! Files outside of these directories are ignored. Non-markdown files in a content directory are
+//! served next to its pages.
+//!
+//! Pages may start with front matter, either YAML between `---` lines, TOML between `+++` lines
+//! (as written by Hugo) or a JSON object, which has to start with a quoted key so pages starting
+//! with Liquid's `{{` or `{%` aren't mistaken for one:
+//! ```toml
+//! +++
+//! title = "Hello"
+//! date = 2022-05-03
+//! tags = ["intro"]
+//! template = "page.liquid"
+//! +++
+//! ```
+//! Front matter is optional, and so is every key of it. Without a `title`, a page is titled by
+//! its first `#` heading, or else by its file name (`hello-world.md` is `Hello world`). Without a
+//! `date`, the date a file name starts with is used (`2022-05-03-hello-world.md`), or else when
+//! the file was last modified. Without a `template`, the one of its content directory is used:
+//! ```yaml
+//! layout:
+//!   - dir: content
+//!     role: Content
+//!     template: page.liquid
+//! ```
+//! so plain markdown notes can be dropped into `content` as they are.
+//!
+//! A page with front matter that can't be parsed is left out of the revision, the rest of it is
+//! still built. Its error, with the line and column it is at, is logged with the build.
+//!
+//! Keys other than `title`, `date`, `tags`, `template` and `draft` are kept as `page.extra` in
+//! templates, and in the `extra` JSON column of `pages` for queries, e.g.
+//! `json_extract(extra, '$.weight')`.
+//!
+//! Dates are either RFC 3339 (`2022-05-03T10:00:00+02:00`) or a local date and time
+//! (`2022-05-03`, `2022-05-03 10:00`), which is taken to be in `timezone` (`UTC` by default, an
+//! offset like `"+02:00"` or a name like `Europe/Paris`, which follows daylight saving time,
+//! `TIMEZONE` in the environment). `page.date` is the date in UTC as RFC 3339, to the second, so
+//! dates sort correctly in queries, and `page.date_original` is the date as written.
+//!
+//! Hidden files, editor leftovers (`*~`, `*.swp`, ...) and anything matched by a `.gitignore` or
+//! `.emphasizeignore` file of the content directory or of any directory in it are never part of a
+//! revision, neither are files matching the `ignore` globs of the config. Themes are read the same
+//! way, with their own ignore files, those of the content directory don't apply to them:
+//! ```yaml
+//! ignore: ["*.bak", "drafts/"]
+//! ```
+//!
+//! # Themes
+//! Templates, stylesheets and static files can come from theme directories shared by several
+//! sites:
+//! ```yaml
+//! themes: [../house-theme]
+//! ```
+//! A theme is laid out like the content directory (`templates/`, `sass/` and `static/` by
+//! default). Its files are part of every revision as if they were in the content directory,
+//! unless the content directory has a file at the same path, which wins. With several themes the
+//! first one listed wins. Themes are watched for changes like the content directory.
+//!
+//! # Serving
+//! By default the site is served on `0.0.0.0:8080`. The `server` section of the config file
+//! changes that:
+//! ```yaml
+//! server:
+//!   listen: ["127.0.0.1:8080", "[::1]:8080"]
+//!   unix_socket: /run/emphasize.sock
+//!   tls:
+//!     cert: cert.pem
+//!     key: key.pem
+//! ```
+//! or `LISTEN` (comma separated), `UNIX_SOCKET`, `TLS_CERT` and `TLS_KEY` in the environment, or
+//! the `--listen` and `--unix-socket` flags. TLS applies to the TCP addresses only.
+//!
+//! # Multiple sites
+//! One process can serve several sites, picked by the `Host` header of each request. Each site
+//! has its own content, database and cache, and is watched and built on its own:
+//! ```yaml
+//! sites:
+//!   - name: blog
+//!     hosts: [blog.example.com]
+//!     content_dir: blog
+//!     db: .emphasize/blog.db
+//!     cache_dir: .emphasize/blog-cache/
+//!   - name: docs
+//!     hosts: [docs.example.com]
+//!     content_dir: docs
+//!     db: .emphasize/docs.db
+//!     cache_dir: .emphasize/docs-cache/
+//!     preview: true
+//! ```
+//! Sites may also set their own `export_dir` and `preview`, everything else comes from the top
+//! level. Commands other than `serve` and `check` work on one site, chosen with `--site`.
+//!
+//! # Previews
+//! With `preview` set to true (or `PREVIEW=1`), any revision can be browsed under
+//! `/_rev/<revision>/`, links inside the rendered pages stay within the preview. Sending an
+//! `X-Emphasize-Revision` header does the same for a single request. Both are off by default, as
+//! they would keep rolled back revisions reachable: previews are not found and the header is
+//! ignored. Templates get the `revision` they are rendered for, so queries can be limited to it:
+//! ```liquid
+//! {% assign posts = "SELECT pages.* FROM pages JOIN revision_files USING (hash, path)
+//!   WHERE revision = ?1" | query: [revision] %}
+//! ```
+//!
+//! # Commands
+//! - `serve` serves the site, watching for changes unless `--read-only` is passed. Read-only
+//!   servers still pick up revisions built by other processes sharing the database, so one
+//!   builder can feed several of them.
+//!   Changes are built into revisions one at a time, in the order they happened. Changes made
+//!   while a revision is building are built together into the next one, unless
+//!   `coalesce_builds` is false (or `COALESCE_BUILDS=0`), which gives each batch its own revision.
+//!   While watching, pages load a script that shows a failed build over the page, with its
+//!   error, until a later build succeeds. Pages a build left out are shown the same way. The same
+//!   status is sent to `/sse` as `build-failed` and `build-succeeded` events next to the usual
+//!   `reload`.
+//! - `build` walks the content directory once, builds a revision and exits.
+//! - `export` renders a revision into `export_dir` (`public` by default) as plain files, ready to
+//!   be uploaded to any static host.
+//! - `revisions list` and `revisions show` inspect the revisions in the database.
+//!   `revisions builds` lists the latest builds with their status, timings and, for failed ones,
+//!   the error, along with the errors of the pages each build left out. Servers watching for
+//!   changes serve the same log as JSON under `/_emphasize/builds`. Builds still running after an
+//!   hour are taken for interrupted and logged as failed when a builder starts. A failed build
+//!   keeps its revision number, the revision is simply never served.
+//! - `rollback`, `pin` and `unpin` move the served revision without rebuilding anything.
+//! - `diff` shows the files, routes and pages that changed between two revisions.
+//! - `check` validates the config, showing where each value came from (default, file, env or
+//!   command line), and that the content directory, cache directory and database are usable.
+//! - `gc` deletes all but the newest `keep_revisions` (10 by default) revisions, except for
+//!   labelled ones (see `revisions label`), along with the files and cache blobs only they used.
+//!
+//! Without a command, the configured `operating_mode` (`ReadWrite`, `ReadOnly`, `Build` or
+//! `Export`) is used. `Build` does the same as the `build` command, so CI can produce a database
+//! to ship without starting a server, a failed build exits with a non-zero code. The
+//! `--cache-dir`, `--db`, `--content-dir` and `--debug` flags override the config file and
+//! environment for every command.
 
-#![feature(generic_associated_types)]
 #![deny(missing_docs)]
 use std::{
-    env,
     ops::Deref,
     path::{Component, Path, PathBuf},
     sync::{mpsc, Arc},
 };
 
-use config::Config;
+use clap::Parser as _;
+use cli::{Cli, Command};
+use config::{Config, Site};
 use db::{
+    active_revision::ActiveRevision,
     input_files::InputFile,
     migrations::MigrateSum,
     pages::{Page, PageTag},
     revision_files::RevisionFile,
+    revision_labels::RevisionLabel,
     revision_routes::RevisionRoute,
     revision_stylesheet::RevisionStylesheet,
+    revisions::RevisionBuild,
 };
-use http::route_with_catch;
+use futures_util::future::try_join_all;
+use http::{serve, Sites};
 use liquid::Parser;
 use notify::{RecommendedWatcher, Watcher};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use tide::{sse, Request};
-use tokio::sync::watch;
 
 use crate::{
     config::{ConfigBuilder, OperatingMode},
     db::make_db_pool,
+    export::export_revision,
     filters::{FilterSum, Filterable, Markdown, Query},
     http::State,
-    walk::{process_walker_events, process_watch_events, walk_assets},
+    reload::poll_status,
+    walk::{build_revision, process_walker_events, process_watch_events, walk_assets},
 };
 
+mod cli;
 mod config;
+mod date;
 mod db;
+mod diff;
+mod export;
 mod filters;
 mod frontmatter;
+mod gc;
 mod http;
+mod layout;
+mod reload;
+mod render;
 mod walk;
 
 type Result<T, E = eyre::Error> = std::result::Result<T, E>;
@@ -73,7 +240,19 @@ type Migrations = MigrateSum<
     InputFile,
     MigrateSum<
         RevisionFile,
-        MigrateSum<Page, MigrateSum<PageTag, MigrateSum<RevisionRoute, RevisionStylesheet>>>,
+        MigrateSum<
+            Page,
+            MigrateSum<
+                PageTag,
+                MigrateSum<
+                    RevisionRoute,
+                    MigrateSum<
+                        RevisionStylesheet,
+                        MigrateSum<ActiveRevision, MigrateSum<RevisionLabel, RevisionBuild>>,
+                    >,
+                >,
+            >,
+        >,
     >,
 >;
 
@@ -82,19 +261,78 @@ async fn main() -> Result<()> {
     pretty_env_logger::init();
     color_eyre::install()?;
 
-    let mut args = env::args();
-    let config_file = args.nth(1);
+    let cli = Cli::parse();
 
     log::info!("Opening config...");
     let mut config_builder = ConfigBuilder::new();
-    if let Some(file) = &config_file {
+    if let Some(file) = &cli.config {
         config_builder = config_builder.with_file(file)?;
     }
-    let config = Arc::new(config_builder.with_envs()?.build_with_defaults());
+    let config = Arc::new(
+        config_builder
+            .with_envs()?
+            .with_args(&cli.config_args())
+            .build_with_defaults(),
+    );
 
     log::info!("Working with: {:?}", config);
 
-    log::info!("Connecting to database: {}...", config.db().display());
+    if let Some(Command::Check) = cli.command {
+        return cli::check(&config);
+    }
+
+    let sites = match &cli.site {
+        Some(name) => vec![config.site(Some(name))?],
+        None => config.sites(),
+    };
+
+    match cli.command {
+        None | Some(Command::Serve { .. }) => match config.operating_mode() {
+            OperatingMode::ReadOnly | OperatingMode::ReadWrite => serve_sites(&config, sites).await,
+            OperatingMode::Build => {
+                for site in sites {
+                    let (pool, _) = open_site(&site)?;
+                    build(site.config, pool).await?;
+                }
+                Ok(())
+            }
+            OperatingMode::Export => {
+                for site in sites {
+                    let (pool, templater) = open_site(&site)?;
+                    export(site.config, pool, templater, None).await?;
+                }
+                Ok(())
+            }
+        },
+        Some(command) => {
+            let site = config.site(cli.site.as_deref())?;
+            let (pool, templater) = open_site(&site)?;
+            let config = site.config;
+            match command {
+                Command::Build => build(config, pool).await,
+                Command::Export { revision, .. } => export(config, pool, templater, revision).await,
+                Command::Revisions { command } => cli::revisions(&command, &pool),
+                Command::Rollback { revision } => cli::rollback(revision, &pool),
+                Command::Pin { revision } => cli::pin(revision, &pool),
+                Command::Unpin => cli::unpin(&pool),
+                Command::Diff { from, to, json } => cli::diff(from, to, json, &pool),
+                Command::Gc { dry_run, .. } => cli::gc(&config, &pool, dry_run),
+                Command::Serve { .. } | Command::Check => unreachable!(),
+            }
+        }
+    }?;
+
+    Ok(())
+}
+
+/// Connect to a site's database and set up its templater.
+fn open_site(site: &Site) -> Result<(Pool<SqliteConnectionManager>, Parser)> {
+    let config = &site.config;
+    log::info!(
+        "Connecting to database of site {}: {}...",
+        site.name,
+        config.db().display()
+    );
     let pool = make_db_pool::<Migrations>(Path::new(config.db()))?;
 
     log::info!("Setting up liquid...");
@@ -105,21 +343,44 @@ async fn main() -> Result<()> {
     )
     .build()?;
 
-    match config.operating_mode() {
-        OperatingMode::ReadOnly => without_watch(config, pool, templater).await,
-        OperatingMode::ReadWrite => with_watch(config, pool, templater).await,
-    }?;
+    Ok((pool, templater))
+}
+
+type Task = tokio::task::JoinHandle<Result<()>>;
+
+/// Serve every site from one server, each with its own database, watcher and revisions.
+async fn serve_sites(config: &Config, sites: Vec<Site>) -> Result<()> {
+    let mut tasks = Vec::new();
+    let mut states = Vec::new();
+    for site in sites {
+        let (pool, templater) = open_site(&site)?;
+        let (state, site_tasks) = match config.operating_mode() {
+            OperatingMode::ReadWrite => with_watch(site.config, pool, templater)?,
+            _ => without_watch(site.config, pool, templater)?,
+        };
+        tasks.extend(site_tasks);
+        states.push((site.hosts, state));
+    }
+
+    let server = config.server().clone();
+    tasks.push(tokio::spawn(async move {
+        serve(Sites::new(states), server).await?;
+        Ok(())
+    }));
+
+    try_join_all(tasks.into_iter().map(|task| async move { task.await? })).await?;
 
     Ok(())
 }
 
-async fn with_watch(
+fn with_watch(
     config: Arc<Config>,
     pool: Pool<SqliteConnectionManager>,
     templater: Parser,
-) -> Result<()> {
+) -> Result<(State, Vec<Task>)> {
     let (mut walker_tx, walker_rx) = mpsc::channel();
-    let (reload_tx, reload_rx) = watch::channel::<usize>(0);
+    let (reload_tx, reload_rx) = reload::channel(&*pool.get()?)?;
+    let reload_tx = Arc::new(reload_tx);
 
     // Walk the assets on startup, just to update the db prematurely in case of changes when not running.
     walk_assets(&config, walker_tx.clone())?;
@@ -130,85 +391,68 @@ async fn with_watch(
             .expect("source for watch event dropped before sender, halp!");
     })?;
     watcher.watch(config.content_dir(), notify::RecursiveMode::Recursive)?;
+    for theme in config.themes() {
+        watcher.watch(theme, notify::RecursiveMode::Recursive)?;
+    }
 
     let walker_config = config.clone();
     let watch_config = config.clone();
 
     let walker_pool = pool.clone();
+    let walker_reload_tx = reload_tx.clone();
 
     let walk_task = tokio::task::spawn_blocking(move || {
-        process_walker_events(walker_config, walker_pool, walker_rx, reload_tx)
+        process_walker_events(walker_config, walker_pool, walker_rx, walker_reload_tx)
     });
     let watch_task = tokio::task::spawn_blocking(move || {
+        // Keep the watcher alive for as long as its events are processed.
+        let _watcher = watcher;
         process_watch_events(watch_config, source, &mut walker_tx)
     });
+    let poll_task = tokio::spawn(poll_status(pool.clone(), reload_tx));
 
-    let mut app = tide::with_state(State {
+    let state = State {
         db: pool,
         templater,
         config,
         reload_rx,
-    });
-    app.with(tide::log::LogMiddleware::new());
-
-    app.at("/sse")
-        .get(sse::endpoint(|req: Request<State>, sender| async move {
-            let mut reload_rx = req.state().reload_rx.clone();
-            reload_rx.borrow_and_update();
-            reload_rx.changed().await?;
-            sender.send("reload", "reload", None).await?;
-            Ok(())
-        }));
-    app.at("/*").get(route_with_catch);
-
-    let app_handle = tokio::spawn(app.listen("0.0.0.0:8080"));
-
-    let result = tokio::try_join!(walk_task, watch_task, app_handle)?;
-
-    result.0?;
-    result.1?;
-    result.2?;
-
-    Ok(())
+    };
+    Ok((state, vec![walk_task, watch_task, poll_task]))
 }
 
-async fn without_watch(
+fn without_watch(
     config: Arc<Config>,
     pool: Pool<SqliteConnectionManager>,
     templater: Parser,
-) -> Result<()> {
-    let (reload_tx, reload_rx) = watch::channel::<usize>(0);
-    let mut counter = 0;
+) -> Result<(State, Vec<Task>)> {
+    let (reload_tx, reload_rx) = reload::channel(&*pool.get()?)?;
+    let poll_task = tokio::spawn(poll_status(pool.clone(), Arc::new(reload_tx)));
 
-    let commit_hook = move || {
-        reload_tx.send(counter).unwrap();
-        counter += counter;
-        false
-    };
-
-    pool.get()?.commit_hook(Some(commit_hook));
-
-    let mut app = tide::with_state(State {
+    let state = State {
         db: pool,
         templater,
         config,
         reload_rx,
-    });
-    app.with(tide::log::LogMiddleware::new());
+    };
+    Ok((state, vec![poll_task]))
+}
 
-    app.at("/sse")
-        .get(sse::endpoint(|req: Request<State>, sender| async move {
-            let mut reload_rx = req.state().reload_rx.clone();
-            reload_rx.borrow_and_update();
-            reload_rx.changed().await?;
-            sender.send("reload", "reload", None).await?;
-            Ok(())
-        }));
-    app.at("/*").get(route_with_catch);
+async fn build(config: Arc<Config>, pool: Pool<SqliteConnectionManager>) -> Result<()> {
+    tokio::task::spawn_blocking(move || build_revision(config, pool)).await??;
 
-    let app_handle = tokio::spawn(app.listen("0.0.0.0:8080"));
+    Ok(())
+}
 
-    app_handle.await??;
+async fn export(
+    config: Arc<Config>,
+    pool: Pool<SqliteConnectionManager>,
+    templater: Parser,
+    revision: Option<usize>,
+) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        export_revision(&config, &pool, &templater, revision, config.export_dir())
+    })
+    .await??;
 
     Ok(())
 }
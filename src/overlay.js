@@ -0,0 +1,42 @@
+// Injected into pages while watching for changes, shows failed builds and skipped pages over the
+// page.
+(function () {
+    var overlay = null;
+
+    function hide() {
+        if (overlay) {
+            overlay.remove();
+            overlay = null;
+        }
+    }
+
+    function show(build) {
+        hide();
+        overlay = document.createElement("div");
+        overlay.id = "emphasize-build-overlay";
+        overlay.style.cssText =
+            "position:fixed;inset:0;z-index:2147483647;overflow:auto;padding:2em;" +
+            "background:rgba(20,20,20,0.92);color:#eee;font-family:monospace;";
+        var title = document.createElement("div");
+        title.style.cssText = "font-size:1.4em;margin-bottom:1em;color:#f77;";
+        title.textContent = build.pages_skipped
+            ? "Revision " + build.revision + " was built without some pages"
+            : "Building revision " + build.revision + " failed";
+        var error = document.createElement("pre");
+        error.style.cssText = "white-space:pre-wrap;";
+        // Escaped and colored by the server.
+        error.innerHTML = build.error;
+        overlay.appendChild(title);
+        overlay.appendChild(error);
+        document.body.appendChild(overlay);
+    }
+
+    var source = new EventSource("/sse");
+    source.addEventListener("build-failed", function (e) {
+        show(JSON.parse(e.data));
+    });
+    source.addEventListener("build-succeeded", hide);
+    source.addEventListener("reload", function () {
+        location.reload();
+    });
+})();
@@ -0,0 +1,100 @@
+use std::{sync::Arc, time::Duration};
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::{
+    db::{
+        active_revision::ActiveRevision,
+        revisions::{BuildStatus, RevisionBuild},
+    },
+    Result,
+};
+
+/// What connected browsers are kept up to date with.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SiteStatus {
+    /// The revision being served.
+    pub revision: usize,
+    /// The latest finished build, if it failed or left pages out.
+    pub failed_build: Option<FailedBuild>,
+}
+
+/// A build that failed or left pages out, shown over the page until a later build succeeds
+/// without leaving any out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FailedBuild {
+    pub revision: usize,
+    pub error: String,
+    /// Whether the revision was built, only without some of its pages.
+    pub pages_skipped: bool,
+}
+
+impl SiteStatus {
+    fn load(db: &Connection) -> Result<Self> {
+        let revision = ActiveRevision::resolve(db)?.unwrap_or_default();
+        let failed_build = RevisionBuild::latest_finished(db)?.and_then(|b| match b.status {
+            BuildStatus::Failed => Some(FailedBuild {
+                revision: b.revision,
+                error: b.error.unwrap_or_default(),
+                pages_skipped: false,
+            }),
+            _ if !b.page_errors.is_empty() => Some(FailedBuild {
+                revision: b.revision,
+                error: b.page_errors.join("\n"),
+                pages_skipped: true,
+            }),
+            _ => None,
+        });
+        Ok(Self {
+            revision,
+            failed_build,
+        })
+    }
+}
+
+/// Create the channel connected browsers wait on, starting at the current status.
+pub fn channel(
+    db: &Connection,
+) -> Result<(watch::Sender<SiteStatus>, watch::Receiver<SiteStatus>)> {
+    Ok(watch::channel(SiteStatus::load(db)?))
+}
+
+/// Tell connected browsers to reload if the active revision moved, or that a build failed or
+/// recovered.
+pub fn notify_status(db: &Connection, reload_tx: &watch::Sender<SiteStatus>) -> Result<()> {
+    let status = SiteStatus::load(db)?;
+    if *reload_tx.borrow() != status {
+        if reload_tx.borrow().revision != status.revision {
+            log::info!("Now serving revision {}", status.revision);
+        }
+        reload_tx.send_replace(status);
+    }
+    Ok(())
+}
+
+/// Keep checking the status, so revisions and builds made by other processes (like `rollback`
+/// or a separate builder writing to the same database) still reach connected browsers.
+///
+/// `PRAGMA data_version` changes whenever another connection commits, so the status is only
+/// looked up again after a write.
+pub async fn poll_status(
+    pool: Pool<SqliteConnectionManager>,
+    reload_tx: Arc<watch::Sender<SiteStatus>>,
+) -> Result<()> {
+    // data_version is per connection, so hold on to the same one.
+    let conn = pool.get()?;
+    let mut last_version = None;
+    let mut interval = tokio::time::interval(Duration::from_millis(250));
+    loop {
+        interval.tick().await;
+        let version: i64 = conn.query_row("PRAGMA data_version", [], |r| r.get(0))?;
+        if last_version != Some(version) {
+            last_version = Some(version);
+            notify_status(&conn, &reload_tx)?;
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::path::Path;
+
+use liquid::Parser;
+use regex::Regex;
+use rusqlite::{params, Connection};
+use serde_rusqlite::from_rows;
+use tide::{
+    http::{mime, Mime},
+    StatusCode,
+};
+
+use crate::{
+    config::Config,
+    db::{
+        input_files::InputFile,
+        pages::Page,
+        revision_routes::{RevisionRoute, RevisionRouteKind},
+        revision_stylesheet::RevisionStylesheet,
+    },
+    layout::Layout,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Route Not Found")]
+    RouteNotFound,
+    #[error("Page Not Found")]
+    PageNotFound,
+    #[error("Content Not Found")]
+    ContentNotFound,
+    #[error("No Template")]
+    NoTemplate,
+    #[error("Template Not Found: {0}")]
+    TemplateNotFound(String),
+    #[error("Stylesheet Not Found")]
+    StylesheetNotFound,
+    #[error("Not Implemented for: {0:?}")]
+    NotImplemented(RevisionRouteKind),
+    #[error(transparent)]
+    Sql(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Row(#[from] serde_rusqlite::Error),
+    #[error(transparent)]
+    Liquid(#[from] liquid::Error),
+    #[error(transparent)]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl Error {
+    /// The HTTP status this error should be reported with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::RouteNotFound | Error::StylesheetNotFound => StatusCode::NotFound,
+            Error::NotImplemented(_) => StatusCode::NotImplemented,
+            _ => StatusCode::InternalServerError,
+        }
+    }
+
+    /// Turn this into a tide error with the matching status.
+    pub fn into_tide(self) -> tide::Error {
+        tide::Error::new(self.status(), self)
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The output of rendering a single route.
+#[derive(Debug)]
+pub struct Rendered {
+    pub mime: Mime,
+    pub body: Vec<u8>,
+}
+
+impl Rendered {
+    /// Point root relative links in HTML and CSS at `base_url` instead of `/`.
+    pub fn rebase(self, base_url: &str) -> Self {
+        let re = if self.mime.essence() == mime::HTML.essence() {
+            Regex::new(r#"(?P<lead>\b(?:href|src|action)\s*=\s*["']?)/(?P<next>[^/]|$)"#)
+        } else if self.mime.essence() == mime::CSS.essence() {
+            Regex::new(r#"(?P<lead>\burl\(\s*["']?)/(?P<next>[^/]|$)"#)
+        } else {
+            return self;
+        }
+        .unwrap();
+
+        let body = match std::str::from_utf8(&self.body) {
+            Ok(body) => re
+                .replace_all(body, format!("${{lead}}{}${{next}}", base_url).as_str())
+                .into_owned()
+                .into_bytes(),
+            Err(_) => return self,
+        };
+        Self { body, ..self }
+    }
+
+    /// Load a script from HTML pages, right before `</body>` or at the end if there is none.
+    pub fn with_script(self, src: &str) -> Self {
+        if self.mime.essence() != mime::HTML.essence() {
+            return self;
+        }
+        let tag = format!(r#"<script src="{}"></script>"#, src);
+        let mut body = match String::from_utf8(self.body) {
+            Ok(body) => body,
+            Err(e) => {
+                return Self {
+                    body: e.into_bytes(),
+                    ..self
+                }
+            }
+        };
+        match body.to_ascii_lowercase().rfind("</body>") {
+            Some(at) => body.insert_str(at, &tag),
+            None => body.push_str(&tag),
+        }
+        Self {
+            body: body.into_bytes(),
+            ..self
+        }
+    }
+}
+
+/// Look up the route for `path` in the given revision.
+pub fn find_route(conn: &Connection, revision: usize, path: &str) -> Result<RevisionRoute> {
+    let mut stmt = conn.prepare(
+        "
+        SELECT * FROM revision_routes
+        WHERE revision = ?1
+        AND route_path = ?2
+        LIMIT 1;
+    ",
+    )?;
+
+    let mut routes = from_rows::<RevisionRoute>(stmt.query(params![revision, path])?);
+    Ok(routes.next().ok_or(Error::RouteNotFound)??)
+}
+
+/// Render a route of a revision into its final bytes.
+///
+/// `base_url` is where the site is mounted (`/` unless previewing), it is available to templates
+/// as `base_url`, next to the `revision` being rendered.
+pub fn render_route(
+    config: &Config,
+    conn: &Connection,
+    templater: &Parser,
+    route: &RevisionRoute,
+    base_url: &str,
+) -> Result<Rendered> {
+    match route.kind {
+        RevisionRouteKind::Page => render_page(config.layout(), conn, templater, route, base_url),
+        RevisionRouteKind::StaticAsset => render_static_asset(config.cache_dir(), conn, route),
+        RevisionRouteKind::Stylesheet => render_stylesheet(conn, route),
+        kind => Err(Error::NotImplemented(kind)),
+    }
+}
+
+fn input_file(conn: &Connection, hash: &str, path: &str) -> Result<InputFile> {
+    let mut stmt = conn.prepare(
+        "
+        SELECT *
+        FROM input_files
+        WHERE hash = ?1 AND path = ?2
+    ",
+    )?;
+
+    let mut files = from_rows::<InputFile>(stmt.query(params![hash, path])?);
+    Ok(files.next().ok_or(Error::ContentNotFound)??)
+}
+
+fn render_page(
+    layout: &Layout,
+    conn: &Connection,
+    templater: &Parser,
+    route: &RevisionRoute,
+    base_url: &str,
+) -> Result<Rendered> {
+    let page = {
+        let mut stmt = conn.prepare(
+            "
+            SELECT *
+            FROM pages
+            WHERE hash = ?1 AND path = ?2
+        ",
+        )?;
+
+        let mut pages = from_rows::<Page>(stmt.query(params![route.hash, route.path])?);
+        let mut page: Page = pages.next().ok_or(Error::PageNotFound)??;
+        // The layout may have moved the page since it was stored.
+        page.route_path = route.route_path.clone();
+        page
+    };
+
+    let template_path = page
+        .template
+        .clone()
+        .or_else(|| layout.page_template(&page.path).map(str::to_string))
+        .ok_or(Error::NoTemplate)?;
+
+    let input_file = input_file(conn, &route.hash, &route.path)?;
+    // Now get the contents without the frontmatter
+    let content = input_file.contents.into_iter().skip(page.content_offset);
+
+    // Now get the template, from the first templates directory that has it
+    let template_file: InputFile = {
+        let mut stmt = conn.prepare(
+            "
+            SELECT input_files.*
+            FROM input_files
+            INNER JOIN revision_files
+            ON revision_files.hash = input_files.hash AND revision_files.path = input_files.path
+            WHERE input_files.path = ?1 AND revision_files.revision = ?2
+        ",
+        )?;
+
+        let mut found = None;
+        for path in layout.template_paths(&template_path) {
+            let mut files = from_rows::<InputFile>(stmt.query(params![path, route.revision])?);
+            if let Some(file) = files.next() {
+                found = Some(file?);
+                break;
+            }
+        }
+        found.ok_or_else(|| Error::TemplateNotFound(template_path.clone()))?
+    };
+
+    // Render it
+    let template = templater.parse(std::str::from_utf8(&template_file.contents)?)?;
+    let html = template.render(&liquid::object!({
+        "source": std::str::from_utf8(&content.collect::<Vec<_>>())?,
+        "page": page,
+        "base_url": base_url,
+        "revision": route.revision,
+    }))?;
+
+    Ok(Rendered {
+        mime: mime::HTML,
+        body: html.into_bytes(),
+    })
+}
+
+fn render_static_asset(
+    cache_dir: &Path,
+    conn: &Connection,
+    route: &RevisionRoute,
+) -> Result<Rendered> {
+    let input_file = input_file(conn, &route.hash, &route.path)?;
+
+    let mime = if input_file.path.ends_with(".png") {
+        mime::PNG
+    } else if input_file.path.ends_with(".js") {
+        mime::JAVASCRIPT
+    } else {
+        mime::PLAIN
+    };
+
+    let body = if input_file.inline {
+        // Just use the contents field
+        input_file.contents
+    } else {
+        // Look it up from the cache
+        std::fs::read(cache_dir.join(&input_file.hash))?
+    };
+
+    Ok(Rendered { mime, body })
+}
+
+fn render_stylesheet(conn: &Connection, route: &RevisionRoute) -> Result<Rendered> {
+    let stylesheet = {
+        let mut stmt = conn.prepare(
+            "
+            SELECT * FROM revision_stylesheets
+            WHERE revision = ?1 AND name = ?2;
+        ",
+        )?;
+
+        let mut stylesheets =
+            from_rows::<RevisionStylesheet>(stmt.query(params![route.revision, route.path])?);
+        stylesheets.next().ok_or(Error::StylesheetNotFound)?
+    }?;
+
+    Ok(Rendered {
+        mime: mime::CSS,
+        body: stylesheet.data.into_bytes(),
+    })
+}
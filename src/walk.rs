@@ -4,32 +4,39 @@ use std::{
     ops::Deref,
     path::{Path, PathBuf},
     sync::{
-        mpsc::{channel, Receiver, Sender},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
         Arc,
     },
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
 use fallible_iterator::FallibleIterator;
-use ignore::Walk;
+use ignore::WalkBuilder;
 use memmap::MmapOptions;
-use notify::{Event, EventKind};
+use notify::{
+    event::{ModifyKind, RenameMode},
+    Event, EventKind,
+};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use rayon::iter::{ParallelBridge, ParallelIterator};
-use rusqlite::params;
+use rusqlite::{params, Connection, TransactionBehavior};
 use tokio::sync::watch;
 
 use crate::{
     config::Config,
     db::{
+        active_revision::ActiveRevision,
         input_files::InputFile,
         pages::Page,
         revision_files::{RevisionFile, RevisionFileIn},
+        revisions::{BuildMetrics, RevisionBuild},
         Insertable,
     },
     normalize_path,
+    reload::{notify_status, SiteStatus},
     walk::{
+        ignores::{Ignores, IGNORE_FILE},
         revision_route::{compile_stylesheets, create_page_routes, create_static_asset_routes},
         revision_set::RevisionSet,
     },
@@ -39,6 +46,8 @@ use crate::{
 use self::event::{WalkerEvent, WalkerItem};
 
 pub mod event;
+pub mod ignores;
+pub mod infer;
 pub mod revision_route;
 pub mod revision_set;
 
@@ -47,6 +56,7 @@ pub struct Entry {
     pub disk_path: PathBuf,
     pub logical_path: String,
     pub size: u64,
+    pub modified: SystemTime,
 }
 
 impl Entry {
@@ -63,11 +73,28 @@ impl Entry {
     }
 }
 
-pub fn walk_dir<F, P: AsRef<Path>>(base: P, prefix: &str, f: F) -> Result<()>
+pub fn walk_dir<F, P: AsRef<Path>>(base: P, prefix: &str, ignores: &Ignores, f: F) -> Result<()>
 where
     F: Fn(Entry) -> Result<()>,
 {
-    for result in Walk::new(base.as_ref().join(prefix)) {
+    let filter_base = base.as_ref().to_path_buf();
+    let filter_ignores = ignores.with_rules_above(base.as_ref(), Path::new(prefix));
+    let walk = WalkBuilder::new(base.as_ref().join(prefix))
+        .standard_filters(false)
+        // The rules files from the prefix down are read as the walk gets to them, the ones
+        // above it are in `ignores`.
+        .git_ignore(true)
+        .require_git(false)
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            match entry.path().strip_prefix(&filter_base) {
+                Ok(path) => !filter_ignores.is_ignored(path, is_dir),
+                Err(_) => true,
+            }
+        })
+        .build();
+    for result in walk {
         match result {
             Ok(entry) if entry.metadata()?.is_file() => {
                 let metadata = entry.metadata()?;
@@ -80,6 +107,7 @@ where
                         .unwrap()
                         .to_string(),
                     size: metadata.len(),
+                    modified: metadata.modified()?,
                 })?
             }
             Err(e) => return Err(e.into()),
@@ -89,6 +117,8 @@ where
     Ok(())
 }
 
+/// Walk `prefixes` of the content directory and of every theme, the content directory's own files
+/// override the themes', and earlier themes override later ones.
 pub fn walk_asset(
     config: &Config,
     prefixes: &[&str],
@@ -97,44 +127,70 @@ pub fn walk_asset(
 ) -> Result<()> {
     let (tx, rx) = channel();
 
-    let mut walk_result = Ok(());
-    let mut send_result = Ok(());
-
-    rayon::scope(|s| {
-        let walk_result = &mut walk_result;
-        let send_result = &mut send_result;
+    let ignores = Ignores::for_layers(config)?;
+    let ignores = &ignores;
 
-        s.spawn(move |_| {
-            *walk_result = (|| -> Result<()> {
+    // The walker gets a thread of its own: as a rayon task it could wait forever for a worker,
+    // when the only one is busy bridging `rx` below.
+    std::thread::scope(|s| {
+        let walker = s.spawn(move || -> Result<()> {
+            let layers = config.layers().collect::<Vec<_>>();
+            for (i, layer) in layers.iter().enumerate() {
                 for &prefix in prefixes {
-                    walk_dir(config.content_dir(), prefix, |entry| {
-                        tx.send(entry)?;
+                    let themed = i > 0;
+                    if themed && !config.layout().is_themeable(prefix)
+                        || !layer.join(prefix).exists()
+                    {
+                        continue;
+                    }
+                    walk_dir(layer, prefix, &ignores[i], |entry| {
+                        let shadowed = layers[..i]
+                            .iter()
+                            .any(|l| l.join(&entry.logical_path).is_file());
+                        if !shadowed {
+                            tx.send(entry)?;
+                        }
                         Ok(())
                     })?;
                 }
-                Ok(())
-            })();
+            }
+            Ok(())
         });
 
-        *send_result = rx
+        let send_result = rx
             .into_iter()
             .par_bridge()
             .map_with(sink, |sink, entry| {
                 process_entry_inner(config, sink, entry, update)
             })
             .collect::<Result<_>>();
-    });
+        let walk_result = walker
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
 
-    Ok(())
+        walk_result.and(send_result)
+    })
 }
 
+/// Walk every directory of the layout, skipping ones that neither the content directory nor a
+/// theme has.
 pub fn walk_assets(config: &Config, sink: Sender<WalkerEvent>) -> Result<()> {
-    walk_asset(
-        config,
-        &["content", "static", "sass", "templates"],
-        sink,
-        true,
-    )
+    let dirs = config
+        .layout()
+        .dirs()
+        .iter()
+        .map(|d| d.dir.trim_matches('/'))
+        .filter(|d| {
+            let exists = config.content_dir().join(d).is_dir()
+                || config.layout().is_themeable(d)
+                    && config.themes().iter().any(|t| t.join(d).is_dir());
+            if !exists {
+                log::debug!("Skipping missing layout dir {}", d);
+            }
+            exists
+        })
+        .collect::<Vec<_>>();
+    walk_asset(config, &dirs, sink, true)
 }
 
 pub fn process_entry_inner(
@@ -175,6 +231,7 @@ pub fn process_entry_inner(
         disk_path: entry.disk_path,
         hash,
         size: entry.size as i64,
+        modified: entry.modified,
         contents: if is_inline {
             contents
         } else {
@@ -189,65 +246,173 @@ pub fn process_entry_inner(
     Ok(())
 }
 
-pub fn process_entry(config: &Config, sink: &mut Sender<WalkerEvent>, entry: Entry) -> Result<()> {
-    process_entry_inner(config, sink, entry, false)
-}
-
+/// Collect walker events into batches, each one becoming a revision.
+///
+/// Batches are built one at a time and in order by a single builder, so revisions never
+/// interleave. With `coalesce_builds`, batches that queue up while a build is running are merged
+/// into the next build.
 pub fn process_walker_events(
     config: Arc<Config>,
     pool: Pool<SqliteConnectionManager>,
     source: Receiver<WalkerEvent>,
-    reload_tx: watch::Sender<usize>,
+    reload_tx: Arc<watch::Sender<SiteStatus>>,
 ) -> Result<()> {
-    let reload_tx = Arc::new(reload_tx);
-    'outer: loop {
-        let (tx, rx) = channel();
-        let mut queue_size: usize = 0;
-        log::info!("Waiting for changes...");
-
-        'inner: loop {
-            let t = source.recv_timeout(Duration::from_millis(250));
-            match t {
-                Ok(event) => {
-                    tx.send(event)?;
-                    queue_size += 1;
-                }
-                Err(_) if queue_size > 0 => {
-                    break 'inner;
-                }
-                Err(_) if queue_size == 0 => {}
-                _ => {
-                    break 'outer;
+    let (batch_tx, batch_rx) = channel();
+    interrupt_unfinished_builds(&pool)?;
+
+    std::thread::scope(|s| {
+        s.spawn(|| build_batches(&config, &pool, batch_rx, &reload_tx));
+
+        'outer: loop {
+            let mut batch = Vec::new();
+            log::info!("Waiting for changes...");
+
+            'inner: loop {
+                match source.recv_timeout(Duration::from_millis(250)) {
+                    Ok(event) => batch.push(event),
+                    Err(RecvTimeoutError::Timeout) if batch.is_empty() => {}
+                    Err(RecvTimeoutError::Timeout) => break 'inner,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        if !batch.is_empty() {
+                            batch_tx.send(batch)?;
+                        }
+                        break 'outer;
+                    }
                 }
             }
+            batch_tx.send(batch)?;
         }
-        drop(tx); // Drop so that process_revision will finish otherwise it will hang
-
-        let p = pool.clone();
-        let c = config.clone();
-        let rtx = reload_tx.clone();
-        tokio::task::spawn_blocking(|| process_revision(c, p, rx, rtx));
-    }
+        // Let the builder finish what is queued.
+        drop(batch_tx);
+        Ok::<_, eyre::Report>(())
+    })?;
 
     log::info!("Finished!");
     Ok(())
 }
 
+/// Builds left running by a builder that went away will never finish, mark them as failed.
+fn interrupt_unfinished_builds(pool: &Pool<SqliteConnectionManager>) -> Result<()> {
+    let interrupted = RevisionBuild::interrupt_unfinished(&*pool.get()?)?;
+    if interrupted > 0 {
+        log::warn!("Marked {} interrupted builds as failed", interrupted);
+    }
+    Ok(())
+}
+
+/// Build a revision from each batch, in the order they were sent.
+fn build_batches(
+    config: &Config,
+    pool: &Pool<SqliteConnectionManager>,
+    batches: Receiver<Vec<WalkerEvent>>,
+    reload_tx: &watch::Sender<SiteStatus>,
+) {
+    while let Ok(mut batch) = batches.recv() {
+        if config.coalesce_builds() {
+            let mut coalesced = 1;
+            while let Ok(more) = batches.try_recv() {
+                batch.extend(more);
+                coalesced += 1;
+            }
+            if coalesced > 1 {
+                log::info!("Coalesced {} batches of changes", coalesced);
+            }
+        }
+
+        // A broken file shouldn't stop later changes from being built.
+        if let Err(e) = process_revision(config, pool, batch, reload_tx) {
+            log::error!("Failed to build revision: {:?}", e);
+        }
+    }
+}
+
+/// Walk every asset once and build a single revision from them.
+pub fn build_revision(config: Arc<Config>, pool: Pool<SqliteConnectionManager>) -> Result<()> {
+    let (tx, rx) = channel();
+    walk_assets(&config, tx)?;
+    interrupt_unfinished_builds(&pool)?;
+
+    // Built from the files on disk alone, so files deleted since the last build are left out.
+    let clear = config
+        .layout()
+        .dirs()
+        .iter()
+        .map(|d| WalkerEvent::Remove(d.dir.trim_matches('/').into()));
+
+    // Nobody is listening for reloads.
+    let (reload_tx, _) = watch::channel(SiteStatus::default());
+    process_revision(&config, &pool, clear.chain(rx), &reload_tx)
+}
+
+/// Build a revision from `events`, logging the build in the `revisions` table whether it
+/// succeeds or not.
 fn process_revision(
-    config: Arc<Config>,
-    pool: Pool<SqliteConnectionManager>,
-    source: Receiver<WalkerEvent>,
-    reload_tx: Arc<watch::Sender<usize>>,
+    config: &Config,
+    pool: &Pool<SqliteConnectionManager>,
+    events: impl IntoIterator<Item = WalkerEvent>,
+    reload_tx: &watch::Sender<SiteStatus>,
 ) -> Result<()> {
+    let events = events.into_iter().collect::<Vec<_>>();
     let start_time = SystemTime::now();
-    let conn = pool.get()?;
-    let tx = conn;
-    // Get the last revision number.
+    let mut conn = pool.get()?;
+    let this_revision = RevisionBuild::start(&mut conn, events.len())?;
+    log::info!("Processing revision {}:", this_revision);
+
+    let mut metrics = BuildMetrics::default();
+    let result = build_into(
+        config,
+        &mut conn,
+        this_revision,
+        events,
+        &mut metrics,
+        start_time,
+    );
+    let duration = SystemTime::now()
+        .duration_since(start_time)
+        .unwrap_or_default();
+    match result {
+        Ok(true) => {
+            log::info!("Finished and commited! {}ms", duration.as_millis());
+            notify_status(&conn, reload_tx)?;
+        }
+        Ok(false) => {
+            log::info!("Cancelled.");
+            RevisionBuild::discard(&conn, this_revision)?;
+        }
+        Err(e) => {
+            let error = format!("{:#}", e);
+            RevisionBuild::finish(
+                &conn,
+                this_revision,
+                &metrics,
+                duration.as_millis() as u64,
+                Some(&error),
+            )?;
+            notify_status(&conn, reload_tx)?;
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Build `this_revision` inside a single transaction, returns `false` if there was nothing to
+/// build.
+fn build_into(
+    config: &Config,
+    conn: &mut Connection,
+    this_revision: usize,
+    events: Vec<WalkerEvent>,
+    metrics: &mut BuildMetrics,
+    start_time: SystemTime,
+) -> Result<bool> {
+    // Everything below is one transaction, so a failure in any phase leaves no trace and readers
+    // never see a half built revision.
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    // The revision the changes apply to.
     let last_revision: Option<usize> =
         tx.query_row("SELECT MAX(revision) FROM revision_files", [], |r| r.get(0))?;
-    let this_revision = last_revision.map(|r| r + 1).unwrap_or_default();
 
-    log::info!("Processing revision {}:", this_revision);
     let mut revision_set = {
         let mut last_revision_stmt = tx.prepare(
             "
@@ -266,18 +431,28 @@ fn process_revision(
     }
 
     log::trace!("Inserting input files");
+    let phase_start = Instant::now();
     {
-        for event in source {
+        for event in events {
             let mut insert_input_file = InputFile::prepare_insert(&tx)?;
             let mut insert_page = Page::prepare_insert(&tx)?;
             log::debug!("Processing: {:?}", event);
-            event.process(&mut revision_set, &mut insert_input_file, &mut insert_page)?;
+            let skipped = event.process(
+                config,
+                &mut revision_set,
+                &mut insert_input_file,
+                &mut insert_page,
+            )?;
+            if let Some(e) = skipped {
+                log::warn!("Leaving out a page: {}", e);
+                metrics.page_errors.push(e.to_string());
+            }
         }
     }
     // End early if the revision set is empty NO OP.
     if revision_set.is_empty() {
-        log::info!("Cancelled.");
-        return Ok(());
+        tx.rollback()?;
+        return Ok(false);
     }
 
     log::trace!("Inserting revision files");
@@ -291,21 +466,40 @@ fn process_revision(
         }
         Ok(())
     })?;
+    metrics.input_files_ms = Some(phase_start.elapsed().as_millis() as u64);
+    metrics.files = Some(revision_set.count());
 
+    let phase_start = Instant::now();
     log::debug!("Creating static assests...");
-    create_static_asset_routes(&tx, this_revision)?;
+    create_static_asset_routes(config.layout(), &tx, this_revision)?;
     log::debug!("Creating page routes...");
-    create_page_routes(&tx, this_revision)?;
+    create_page_routes(config.layout(), &tx, this_revision)?;
+    metrics.routes_ms = Some(phase_start.elapsed().as_millis() as u64);
+    metrics.routes = Some(tx.query_row(
+        "SELECT COUNT(*) FROM revision_routes WHERE revision = ?1",
+        params![this_revision],
+        |r| r.get(0),
+    )?);
+
+    let phase_start = Instant::now();
     log::debug!("Compiling stylesheets...");
-    compile_stylesheets(&config, &tx, this_revision)?;
-    let end_time = SystemTime::now();
-    let duration = end_time.duration_since(start_time).unwrap();
-
-    log::info!("Finished and commited! {}ms", duration.as_millis());
-    let current_value = *reload_tx.borrow();
-    reload_tx.send(current_value + 1)?;
-
-    Ok(())
+    compile_stylesheets(config, &tx, this_revision)?;
+    metrics.stylesheets_ms = Some(phase_start.elapsed().as_millis() as u64);
+
+    ActiveRevision::advance(&tx, this_revision)?;
+    let duration = SystemTime::now()
+        .duration_since(start_time)
+        .unwrap_or_default();
+    RevisionBuild::finish(
+        &tx,
+        this_revision,
+        metrics,
+        duration.as_millis() as u64,
+        None,
+    )?;
+    tx.commit()?;
+
+    Ok(true)
 }
 
 pub fn process_watch_events(
@@ -314,74 +508,341 @@ pub fn process_watch_events(
     sink: &mut Sender<WalkerEvent>,
 ) -> Result<()> {
     let base_path = config.content_dir().canonicalize()?;
+    let mut watched = Watched {
+        config: &config,
+        base_path: &base_path,
+        theme_paths: config
+            .themes()
+            .iter()
+            .filter_map(|t| t.canonicalize().ok())
+            .collect(),
+        ignores: Ignores::for_layers(&config)?,
+    };
     for event in source {
         let event = event?;
+
+        let rules_dirs = event
+            .paths
+            .iter()
+            .filter_map(|p| {
+                let p = normalize_path(p);
+                let logical_path = watched
+                    .theme_paths
+                    .iter()
+                    .chain([&base_path])
+                    .find_map(|layer| p.strip_prefix(layer).ok())?;
+                Ignores::is_rules_file(logical_path)
+                    .then(|| logical_path.parent().map(Path::to_path_buf))?
+            })
+            .collect::<Vec<_>>();
+        if !rules_dirs.is_empty() {
+            log::info!("Ignore rules changed, reloading them");
+            watched.ignores = Ignores::for_layers(&config)?;
+            for dir in rules_dirs {
+                watched.rules_changed(&dir, sink)?;
+            }
+        }
+
         match event.kind {
             EventKind::Create(_) => {
                 for p in event.paths {
-                    if p.is_file() {
-                        let f = File::open(&p)?;
-                        let entry = Entry {
-                            disk_path: p.canonicalize()?,
-                            logical_path: p
-                                .canonicalize()?
-                                .strip_prefix(&base_path)?
-                                .to_str()
-                                .unwrap()
-                                .to_string(),
-                            size: f.metadata()?.len(),
-                        };
-                        drop(f);
-                        process_entry(&config, sink, entry)?;
-                    } else if p.is_dir() {
-                        walk_asset(
-                            &config,
-                            &[p.canonicalize()?
-                                .strip_prefix(&base_path)
-                                .map(|p| p.to_str().unwrap())?],
-                            sink.clone(),
-                            false,
-                        )?;
-                    }
+                    watched.added(&p, sink, false)?;
                 }
             }
             EventKind::Remove(_) => {
                 for p in event.paths {
-                    sink.send(WalkerEvent::Remove(
-                        normalize_path(&p).strip_prefix(&base_path)?.to_path_buf(),
-                    ))?;
+                    watched.removed(&p, sink)?;
                 }
             }
-            EventKind::Modify(_) => {
+            // Every backend reporting the `Both` half of a rename also reports `From` and `To`, so
+            // a rename is handled as removing the old path and adding the new one. Moving out of
+            // the content directory only reports `From`, moving into it only `To` (or `Create`).
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                for p in event.paths {
+                    watched.removed(&p, sink)?;
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
                 for p in event.paths {
-                    if p.is_file() {
-                        let f = File::open(&p)?;
-                        let entry = Entry {
-                            disk_path: p.canonicalize()?,
-                            logical_path: p
-                                .canonicalize()?
-                                .strip_prefix(&base_path)?
-                                .to_str()
-                                .unwrap()
-                                .to_string(),
-                            size: f.metadata()?.len(),
-                        };
-                        drop(f);
-                        process_entry_inner(&config, sink, entry, true)?;
-                    } else if p.is_dir() {
-                        walk_asset(
-                            &config,
-                            &[p.canonicalize()?
-                                .strip_prefix(&base_path)
-                                .map(|p| p.to_str().unwrap())?],
-                            sink.clone(),
-                            true,
-                        )?;
+                    watched.added(&p, sink, false)?;
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {}
+            // Backends that can't tell which side of the rename a path is on.
+            EventKind::Modify(ModifyKind::Name(_)) => {
+                for p in event.paths {
+                    if p.exists() {
+                        watched.added(&p, sink, false)?;
+                    } else {
+                        watched.removed(&p, sink)?;
                     }
                 }
             }
+            EventKind::Modify(_) => {
+                for p in event.paths {
+                    watched.added(&p, sink, true)?;
+                }
+            }
             _ => {}
         }
     }
     Ok(())
 }
+
+/// Turns the paths of watch events into walker events.
+struct Watched<'a> {
+    config: &'a Config,
+    base_path: &'a Path,
+    theme_paths: Vec<PathBuf>,
+    /// The rules of each layer, the content directory's first.
+    ignores: Vec<Ignores>,
+}
+
+/// Which layer a watched path is in.
+enum Layer {
+    /// The content directory, for a path no theme can provide.
+    Content,
+    /// A path themes can provide, relative to the layer it is in.
+    Themed(PathBuf),
+    /// A path of a theme that themes can't provide.
+    Outside,
+}
+
+impl Watched<'_> {
+    fn layer(&self, p: &Path) -> Layer {
+        let p = normalize_path(p);
+        let themeable = |rest: &Path| {
+            rest.to_str()
+                .is_some_and(|r| self.config.layout().is_themeable(r))
+        };
+        // Themes first, in case one is inside the content directory.
+        for theme in &self.theme_paths {
+            if let Ok(rest) = p.strip_prefix(theme) {
+                return match themeable(rest) {
+                    true => Layer::Themed(rest.to_path_buf()),
+                    false => Layer::Outside,
+                };
+            }
+        }
+        match p.strip_prefix(self.base_path) {
+            Ok(rest) if !self.theme_paths.is_empty() && themeable(rest) => {
+                Layer::Themed(rest.to_path_buf())
+            }
+            _ => Layer::Content,
+        }
+    }
+
+    /// Something changed at a path themes can provide, the file that wins may be in another layer
+    /// now, so everything at the path is replaced with a fresh walk of every layer.
+    fn resync(&self, logical_path: &Path, sink: &mut Sender<WalkerEvent>) -> Result<()> {
+        if !(self.wanted_by_any_layer(logical_path, false)
+            || self.wanted_by_any_layer(logical_path, true))
+        {
+            return Ok(());
+        }
+        sink.send(WalkerEvent::Remove(logical_path.to_path_buf()))?;
+        walk_asset(
+            self.config,
+            &[logical_path.to_str().unwrap()],
+            sink.clone(),
+            false,
+        )
+    }
+
+    /// The ignore rules of `logical_dir` changed, everything under it is walked again so files
+    /// that are ignored now are dropped and files that no longer are are added.
+    fn rules_changed(&self, logical_dir: &Path, sink: &mut Sender<WalkerEvent>) -> Result<()> {
+        let dirs = if logical_dir.as_os_str().is_empty() {
+            self.config
+                .layout()
+                .dirs()
+                .iter()
+                .map(|d| d.dir.trim_matches('/'))
+                .collect()
+        } else if self.wanted_by_any_layer(logical_dir, true) {
+            vec![logical_dir.to_str().unwrap()]
+        } else {
+            vec![]
+        };
+        for dir in dirs {
+            sink.send(WalkerEvent::Remove(PathBuf::from(dir)))?;
+            walk_asset(self.config, &[dir], sink.clone(), false)?;
+        }
+        Ok(())
+    }
+
+    /// Paths outside of the layout's directories aren't part of the site, ignored ones are left
+    /// out just like in the initial walk. For paths of the content directory.
+    fn wanted(&self, logical_path: &Path, is_dir: bool) -> bool {
+        self.in_layout(logical_path) && !self.ignores[0].is_ignored_anywhere(logical_path, is_dir)
+    }
+
+    /// Like `wanted`, for paths themes can provide, which only need one layer not to ignore them.
+    fn wanted_by_any_layer(&self, logical_path: &Path, is_dir: bool) -> bool {
+        self.in_layout(logical_path)
+            && self
+                .ignores
+                .iter()
+                .any(|ignores| !ignores.is_ignored_anywhere(logical_path, is_dir))
+    }
+
+    fn in_layout(&self, logical_path: &Path) -> bool {
+        logical_path
+            .to_str()
+            .is_some_and(|p| self.config.layout().resolve(p).is_some())
+    }
+
+    /// A file or directory was created or changed at `p`, if it is a directory everything in it is
+    /// walked.
+    fn added(&self, p: &Path, sink: &mut Sender<WalkerEvent>, update: bool) -> Result<()> {
+        match self.layer(p) {
+            Layer::Content => {}
+            Layer::Themed(logical_path) => return self.resync(&logical_path, sink),
+            Layer::Outside => return Ok(()),
+        }
+        let logical_path = match normalize_path(p).strip_prefix(self.base_path) {
+            Ok(logical_path) => logical_path.to_path_buf(),
+            Err(_) => return Ok(()),
+        };
+        // Checked before touching the file, editors create and delete their temporary files
+        // faster than they can be read.
+        if !(self.wanted(&logical_path, false) || self.wanted(&logical_path, true)) {
+            return Ok(());
+        }
+        match self.read_added(p, &logical_path, sink, update) {
+            Err(e) if is_not_found(&e) => {
+                log::debug!("{} is already gone", p.display());
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn read_added(
+        &self,
+        p: &Path,
+        logical_path: &Path,
+        sink: &mut Sender<WalkerEvent>,
+        update: bool,
+    ) -> Result<()> {
+        let metadata = std::fs::metadata(p)?;
+        if metadata.is_file() {
+            if self.wanted(logical_path, false) {
+                let entry = Entry {
+                    disk_path: p.canonicalize()?,
+                    logical_path: logical_path.to_str().unwrap().to_string(),
+                    size: metadata.len(),
+                    modified: metadata.modified()?,
+                };
+                process_entry_inner(self.config, sink, entry, update)?;
+            }
+        } else if metadata.is_dir() && self.wanted(logical_path, true) {
+            walk_asset(
+                self.config,
+                &[logical_path.to_str().unwrap()],
+                sink.clone(),
+                update,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The file or directory at `p` is gone, along with everything in it.
+    fn removed(&self, p: &Path, sink: &mut Sender<WalkerEvent>) -> Result<()> {
+        match self.layer(p) {
+            Layer::Content => {}
+            Layer::Themed(logical_path) => return self.resync(&logical_path, sink),
+            Layer::Outside => return Ok(()),
+        }
+        let logical_path = normalize_path(p)
+            .strip_prefix(self.base_path)?
+            .to_path_buf();
+        // Whether it was a directory can't be known anymore, so check both.
+        if self.wanted(&logical_path, false) || self.wanted(&logical_path, true) {
+            sink.send(WalkerEvent::Remove(logical_path))?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether an error is about a file that doesn't exist (anymore).
+fn is_not_found(e: &eyre::Report) -> bool {
+    let io_error = match e.downcast_ref::<ignore::Error>() {
+        Some(e) => e.io_error(),
+        None => e.downcast_ref::<std::io::Error>(),
+    };
+    io_error.is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use tokio::sync::watch;
+
+    use super::{
+        event::{WalkerEvent, WalkerItem},
+        process_revision,
+    };
+    use crate::{
+        config::Config,
+        db::{
+            make_db_pool,
+            revisions::{BuildStatus, RevisionBuild},
+        },
+        reload::SiteStatus,
+        Migrations,
+    };
+
+    fn page(path: &str, contents: &str) -> WalkerEvent {
+        WalkerEvent::Add(WalkerItem {
+            inline: true,
+            path: path.to_string(),
+            disk_path: path.into(),
+            hash: format!("{:016x}", seahash::hash(contents.as_bytes())),
+            size: contents.len() as i64,
+            modified: SystemTime::now(),
+            contents: Box::new(contents.as_bytes().to_vec()),
+        })
+    }
+
+    #[test]
+    fn pages_with_invalid_front_matter_are_left_out() {
+        let dir = std::env::temp_dir().join(format!("emphasize-walk-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let pool = make_db_pool::<Migrations>(&dir.join("content.db")).unwrap();
+        let (reload_tx, _) = watch::channel(SiteStatus::default());
+
+        let events = [
+            page("content/good.md", "---\ntitle: Good\n---\nBody\n"),
+            page(
+                "content/bad.md",
+                "---\ntitle: Bad\ndate: someday\n---\nBody\n",
+            ),
+        ];
+        process_revision(&Config::default(), &pool, events, &reload_tx).unwrap();
+
+        let conn = pool.get().unwrap();
+        let build = RevisionBuild::recent(&conn, 1).unwrap().remove(0);
+        assert_eq!(build.status, BuildStatus::Succeeded);
+        assert_eq!(build.error, None);
+        assert_eq!(build.page_errors.len(), 1);
+        assert!(
+            build.page_errors[0].starts_with("content/bad.md:3:7: Invalid YAML front matter"),
+            "{}",
+            build.page_errors[0]
+        );
+        let routes = conn
+            .prepare("SELECT route_path FROM revision_routes WHERE revision = ?1")
+            .unwrap()
+            .query_map([build.revision], |r| r.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .unwrap();
+        assert_eq!(routes, ["good"]);
+
+        drop((conn, pool));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
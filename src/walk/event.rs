@@ -1,15 +1,15 @@
-use std::{ops::Deref, path::PathBuf};
+use std::{ops::Deref, path::PathBuf, time::SystemTime};
 
 use derivative::Derivative;
 
 use crate::{
+    config::Config,
     db::{input_files::InputFileIn, pages::PageIn},
-    frontmatter::FrontMatter,
-    walk::revision_route::to_route_path,
+    frontmatter::{self, FrontMatter},
     Result,
 };
 
-use super::revision_set::RevisionSet;
+use super::{infer, revision_set::RevisionSet};
 
 #[derive(Debug)]
 pub enum WalkerEvent {
@@ -26,21 +26,30 @@ pub struct WalkerItem {
     pub disk_path: PathBuf,
     pub hash: String,
     pub size: i64,
+    /// When the file was last modified, as of when it was read.
+    pub modified: SystemTime,
     #[derivative(Debug = "ignore")]
     pub contents: Box<dyn Deref<Target = [u8]> + Send + Sync>,
 }
 
 impl WalkerEvent {
+    /// Apply the event to `revision_set`, storing the files and pages it adds.
+    ///
+    /// A page with invalid front matter is left out of the revision instead of failing it, its
+    /// error is returned.
     pub fn process(
         self,
+        config: &Config,
         revision_set: &mut RevisionSet,
         insert_input_file: &mut dyn for<'a> FnMut(&'a InputFileIn<'a>) -> Result<()>,
         insert_page: &mut dyn for<'a> FnMut(&'a PageIn<'a>) -> Result<()>,
-    ) -> Result<()> {
+    ) -> Result<Option<frontmatter::Error>> {
+        let mut skipped = None;
         match self {
             WalkerEvent::Add(item) => {
                 log::trace!("Add event: {:?}", item.path);
-                new_input_file(revision_set, insert_input_file, insert_page, item)?;
+                skipped =
+                    new_input_file(config, revision_set, insert_input_file, insert_page, item)?;
             }
             WalkerEvent::Remove(p) => {
                 log::trace!("Remove event: {:?}", p);
@@ -52,21 +61,40 @@ impl WalkerEvent {
                 revision_set.remove_by_path(&item.path);
                 // To ignore files that were only touched, not written.
                 if !already_exists {
-                    new_input_file(revision_set, insert_input_file, insert_page, item)?;
+                    skipped =
+                        new_input_file(config, revision_set, insert_input_file, insert_page, item)?;
                 }
             }
         }
         log::debug!("Processed");
-        Ok(())
+        Ok(skipped)
     }
 }
 
 fn new_input_file(
+    config: &Config,
     rv: &mut RevisionSet,
     iif: &mut dyn for<'a> FnMut(&'a InputFileIn<'a>) -> Result<()>,
     ip: &mut dyn for<'a> FnMut(&'a PageIn<'a>) -> Result<()>,
     item: WalkerItem,
-) -> Result<()> {
+) -> Result<Option<frontmatter::Error>> {
+    let layout = config.layout();
+    // Parsed before anything is stored, so a page that can't be is left out entirely.
+    let page = match layout
+        .is_page(&item.path)
+        .then(|| layout.page_route_path(&item.path))
+        .flatten()
+    {
+        Some(route_path) => {
+            let parsed_contents = std::str::from_utf8(item.contents.as_ref().deref())?;
+            match FrontMatter::parse(&item.path, parsed_contents) {
+                Ok((fm, offset)) => Some((route_path, parsed_contents, fm, offset)),
+                Err(e) => return Ok(Some(e)),
+            }
+        }
+        None => None,
+    };
+
     rv.add(&item.hash, &item.path);
     iif(&InputFileIn {
         hash: &item.hash,
@@ -75,24 +103,32 @@ fn new_input_file(
         size: item.size,
         inline: item.inline,
     })?;
-    if item.path.ends_with(".md") {
+    if let Some((route_path, parsed_contents, fm, offset)) = page {
         log::trace!("Adding page!");
-        let parsed_contents = std::str::from_utf8(item.contents.as_ref().deref())?;
-        let (fm, offset) = FrontMatter::parse(&item.path, parsed_contents)?;
         log::trace!("Got Frontmatter: {:?}", fm);
+        let title = match fm.title {
+            Some(title) => title,
+            None => infer::title(&item.path, &parsed_contents[offset..]),
+        };
+        let date = match fm.date {
+            Some(date) => date,
+            None => infer::date(&item.path, item.modified),
+        };
         ip(&PageIn {
             hash: &item.hash,
             path: &item.path,
-            title: &fm.title,
-            date: &fm.date,
+            title: &title,
+            date: &date.normalized(config.timezone()),
+            date_original: &date.original,
             tags: &fm.tags,
             content_offset: offset,
-            route_path: &to_route_path(&item.path)?,
+            route_path: &route_path,
             template: &fm.template,
             draft: fm.draft,
+            extra: &fm.extra,
         })?;
     } else {
         log::trace!("Not a page");
     }
-    Ok(())
+    Ok(None)
 }
@@ -0,0 +1,229 @@
+use std::path::{Component, Path, PathBuf};
+
+use eyre::WrapErr;
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+
+use crate::{config::Config, Result};
+
+/// Ignore file read from every directory of the content directory and themes, in `.gitignore`
+/// syntax.
+pub const IGNORE_FILE: &str = ".emphasizeignore";
+
+/// Files editors and operating systems leave around, which are never part of a site.
+const DEFAULT_IGNORES: &[&str] = &[
+    "*~",
+    "*.swp",
+    "*.swo",
+    "*.swx",
+    "*.tmp",
+    "#*#",
+    ".#*",
+    "4913",
+    ".DS_Store",
+    "Thumbs.db",
+];
+
+/// Names of the files ignore rules are read from, in any directory.
+const RULES_FILES: &[&str] = &[".gitignore", IGNORE_FILE];
+
+/// Decides which files of a layer, the content directory or a theme, are left out of revisions,
+/// the same way for the initial walk and for watch events.
+///
+/// Hidden files, the defaults above, the `.gitignore` and `.emphasizeignore` files of the layer
+/// and the `ignore` globs from the config are all ignored. The rules files of one layer don't
+/// apply to the others.
+#[derive(Debug, Clone)]
+pub struct Ignores {
+    root: PathBuf,
+    matcher: Gitignore,
+    /// Rules of directories below the root, deepest first, with the directory they are in.
+    nested: Vec<(PathBuf, Gitignore)>,
+}
+
+impl Ignores {
+    /// The rules of `layer`, with the config's globs.
+    pub fn new(config: &Config, layer: &Path) -> Result<Self> {
+        Self::with_globs(layer, config.ignore())
+    }
+
+    /// The rules of every layer, in the order of `Config::layers`.
+    pub fn for_layers(config: &Config) -> Result<Vec<Self>> {
+        config
+            .layers()
+            .map(|layer| Self::new(config, layer))
+            .collect()
+    }
+
+    fn with_globs(layer: &Path, globs: &[String]) -> Result<Self> {
+        // Rooted at the canonical path, so relative paths are never mistaken for being under it.
+        let root = layer.canonicalize().unwrap_or_else(|_| layer.to_path_buf());
+        let mut builder = GitignoreBuilder::new(&root);
+        for line in DEFAULT_IGNORES {
+            builder.add_line(None, line)?;
+        }
+        add_rules_files(&mut builder, &root)?;
+        for glob in globs {
+            builder
+                .add_line(None, glob)
+                .wrap_err_with(|| format!("Invalid ignore glob {:?}", glob))?;
+        }
+
+        Ok(Self {
+            matcher: builder.build()?,
+            root,
+            nested: Vec::new(),
+        })
+    }
+
+    /// These rules along with the rules files of every directory above `logical_dir` in `base`,
+    /// but not the ones of `logical_dir` itself. For walks of `logical_dir`, which read the rules
+    /// files below as they go.
+    pub fn with_rules_above(&self, base: &Path, logical_dir: &Path) -> Self {
+        let nested = logical_dir
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| !dir.as_os_str().is_empty())
+            .filter_map(|dir| {
+                let mut builder = GitignoreBuilder::new(base.join(dir));
+                let matcher = add_rules_files(&mut builder, &base.join(dir))
+                    .and_then(|_| Ok(builder.build()?));
+                match matcher {
+                    Ok(matcher) if !matcher.is_empty() => Some((dir.to_path_buf(), matcher)),
+                    Ok(_) => None,
+                    Err(e) => {
+                        log::warn!("{:#}", e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        Self {
+            nested,
+            ..self.clone()
+        }
+    }
+
+    /// Whether a path relative to the layer is ignored.
+    ///
+    /// Only the rules files of the root and the ones of `with_rules_above` are read, walks read
+    /// the others, paths that don't come from one are checked with `is_ignored_anywhere`. Rules
+    /// of deeper directories win over the ones above them, like with git, except that nothing can
+    /// bring back what the root ignores.
+    pub fn is_ignored<P: AsRef<Path>>(&self, logical_path: P, is_dir: bool) -> bool {
+        let path = logical_path.as_ref();
+        let hidden = path.components().any(|c| match c {
+            Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+            _ => false,
+        });
+        if hidden
+            || self
+                .matcher
+                .matched_path_or_any_parents(path, is_dir)
+                .is_ignore()
+        {
+            return true;
+        }
+        for (dir, matcher) in &self.nested {
+            let rest = match path.strip_prefix(dir) {
+                Ok(rest) => rest,
+                Err(_) => continue,
+            };
+            match matcher.matched_path_or_any_parents(rest, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+
+    /// Whether a path relative to the layer is ignored by the rules files of any directory it is
+    /// in.
+    pub fn is_ignored_anywhere<P: AsRef<Path>>(&self, logical_path: P, is_dir: bool) -> bool {
+        let path = logical_path.as_ref();
+        self.with_rules_above(&self.root, path)
+            .is_ignored(path, is_dir)
+    }
+
+    /// Whether a path relative to a layer is one of the files rules are read from.
+    pub fn is_rules_file<P: AsRef<Path>>(logical_path: P) -> bool {
+        logical_path
+            .as_ref()
+            .file_name()
+            .is_some_and(|name| RULES_FILES.iter().any(|f| name == *f))
+    }
+}
+
+/// Add the rules files of `dir` to `builder`, `.emphasizeignore` last so it wins over
+/// `.gitignore`.
+fn add_rules_files(builder: &mut GitignoreBuilder, dir: &Path) -> Result<()> {
+    for file in RULES_FILES {
+        let path = dir.join(file);
+        if path.is_file() {
+            if let Some(e) = builder.add(&path) {
+                return Err(e).wrap_err_with(|| format!("Invalid {}", path.display()));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, sync::Mutex};
+
+    use super::Ignores;
+    use crate::walk::walk_dir;
+
+    /// A content directory ignoring `*.txt` and a theme without rules of its own, in a directory
+    /// of the test's own.
+    fn layers(test: &str) -> (PathBuf, PathBuf) {
+        let base =
+            std::env::temp_dir().join(format!("emphasize-ignores-{}-{}", std::process::id(), test));
+        let (content, theme) = (base.join("site"), base.join("theme"));
+        for dir in [&content, &theme] {
+            fs::create_dir_all(dir.join("static")).unwrap();
+            fs::write(dir.join("static/a.txt"), "").unwrap();
+        }
+        fs::write(content.join(".gitignore"), "*.txt\n").unwrap();
+        fs::write(content.join("static/.emphasizeignore"), "*.png\n").unwrap();
+        (content, theme)
+    }
+
+    #[test]
+    fn rules_files_only_apply_to_their_layer() {
+        let (content_dir, theme_dir) = layers("layer");
+        let content = Ignores::with_globs(&content_dir, &[]).unwrap();
+        let theme = Ignores::with_globs(&theme_dir, &[]).unwrap();
+
+        assert!(content.is_ignored("static/a.txt", false));
+        assert!(content.is_ignored_anywhere("static/a.png", false));
+        assert!(!theme.is_ignored("static/a.txt", false));
+        assert!(!theme.is_ignored_anywhere("static/a.png", false));
+        // Defaults and hidden files are ignored in every layer.
+        assert!(theme.is_ignored("static/a.txt~", false));
+        assert!(theme.is_ignored("static/.a.txt", false));
+
+        let walked = |dir: &PathBuf, ignores: &Ignores| {
+            let found = Mutex::new(Vec::new());
+            walk_dir(dir, "static", ignores, |entry| {
+                found.lock().unwrap().push(entry.logical_path);
+                Ok(())
+            })
+            .unwrap();
+            found.into_inner().unwrap()
+        };
+        assert!(walked(&content_dir, &content).is_empty());
+        assert_eq!(walked(&theme_dir, &theme), ["static/a.txt"]);
+    }
+
+    #[test]
+    fn config_globs_apply_to_every_layer() {
+        let (_, theme) = layers("globs");
+        let theme = Ignores::with_globs(&theme, &["*.css".to_string()]).unwrap();
+        assert!(theme.is_ignored("static/a.css", false));
+    }
+}
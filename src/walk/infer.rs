@@ -0,0 +1,131 @@
+use std::time::SystemTime;
+
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag};
+
+use crate::date::Date;
+
+/// The title of a page without one in its front matter: its first `#` heading, or else its name
+/// (`2022-05-03-hello-world.md` is `Hello world`).
+pub fn title(path: &str, body: &str) -> String {
+    heading(body).unwrap_or_else(|| {
+        let name = name(path);
+        let words = match split_date(name) {
+            (_, "") => name.to_string(),
+            (_, rest) => rest.replace(['-', '_'], " "),
+        };
+        let mut chars = words.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => words,
+        }
+    })
+}
+
+/// The date of a page without one in its front matter: the one its name starts with, or else
+/// when the file was last modified.
+pub fn date(path: &str, modified: SystemTime) -> Date {
+    split_date(name(path)).0.unwrap_or_else(|| modified.into())
+}
+
+/// The text of the first level 1 heading.
+fn heading(body: &str) -> Option<String> {
+    let mut heading: Option<String> = None;
+    for event in Parser::new(body) {
+        match (event, &mut heading) {
+            (Event::Start(Tag::Heading(HeadingLevel::H1, ..)), None) => {
+                heading = Some(String::new())
+            }
+            (Event::End(Tag::Heading(HeadingLevel::H1, ..)), Some(_)) => break,
+            (Event::Text(text) | Event::Code(text), Some(heading)) => heading.push_str(&text),
+            (Event::SoftBreak, Some(heading)) => heading.push(' '),
+            _ => {}
+        }
+    }
+    heading
+        .map(|heading| heading.trim().to_string())
+        .filter(|heading| !heading.is_empty())
+}
+
+/// The file name of a page without `.md`, or the name of its directory for `index.md`.
+fn name(path: &str) -> &str {
+    let path = path.strip_suffix(".md").unwrap_or(path);
+    let path = path.strip_suffix("/index").unwrap_or(path);
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Split a name like `2022-05-03-slug` into its date and the rest.
+fn split_date(name: &str) -> (Option<Date>, &str) {
+    let (date, rest) = match (name.get(..10), name.get(10..)) {
+        (Some(date), Some(rest)) => (date, rest),
+        _ => return (None, name),
+    };
+    match Date::parse(date) {
+        Some(date) if rest.is_empty() || rest.starts_with(['-', '_']) => {
+            (Some(date), rest.trim_start_matches(['-', '_']))
+        }
+        _ => (None, name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{date, title};
+    use crate::{date::Timezone, frontmatter::FrontMatter};
+
+    fn modified() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_651_572_000)
+    }
+
+    fn date_of(path: &str) -> String {
+        date(path, modified()).normalized(Timezone::default())
+    }
+
+    #[test]
+    fn pages_without_front_matter_are_inferred() {
+        let input = "# Hello *world*\n\nBody\n";
+        let (fm, offset) = FrontMatter::parse("notes.md", input).unwrap();
+        assert!(fm.title.is_none() && fm.date.is_none());
+        assert_eq!(offset, 0);
+        assert_eq!(title("notes.md", &input[offset..]), "Hello world");
+        assert_eq!(date_of("notes.md"), "2022-05-03T10:00:00Z");
+
+        let input = "Body\n";
+        let (_, offset) = FrontMatter::parse("2021-01-02-my_trip.md", input).unwrap();
+        assert_eq!(title("2021-01-02-my_trip.md", &input[offset..]), "My trip");
+        assert_eq!(date_of("2021-01-02-my_trip.md"), "2021-01-02T00:00:00Z");
+    }
+
+    #[test]
+    fn titles_come_from_the_first_level_1_heading() {
+        assert_eq!(
+            title("a.md", "## Sub\n\n# Main `code`\n\n# Other\n"),
+            "Main code"
+        );
+        assert_eq!(title("a.md", "Main\n====\n"), "Main");
+        assert_eq!(title("a.md", "#\n\n## Sub\n"), "A");
+    }
+
+    #[test]
+    fn titles_fall_back_to_the_name() {
+        assert_eq!(title("blog/2022-05-03-hello-world.md", ""), "Hello world");
+        assert_eq!(title("notes/2021-01-02-trip/index.md", ""), "Trip");
+        assert_eq!(title("about.md", ""), "About");
+        // A name that is only a date is kept as it is.
+        assert_eq!(title("2022-05-03.md", ""), "2022-05-03");
+    }
+
+    #[test]
+    fn dates_fall_back_to_the_modified_time() {
+        assert_eq!(
+            date_of("notes/2021-01-02-trip/index.md"),
+            "2021-01-02T00:00:00Z"
+        );
+        assert_eq!(date_of("2022-01-05.md"), "2022-01-05T00:00:00Z");
+        assert_eq!(date_of("about.md"), "2022-05-03T10:00:00Z");
+        // Not a date, or not followed by a separator.
+        assert_eq!(date_of("2022-13-05-hello.md"), "2022-05-03T10:00:00Z");
+        assert_eq!(date_of("2022-01-05hello.md"), "2022-05-03T10:00:00Z");
+    }
+}
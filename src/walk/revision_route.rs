@@ -1,7 +1,4 @@
-use std::{
-    borrow::Cow,
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
 
 use rusqlite::{params, Connection};
 use serde::Deserialize;
@@ -15,11 +12,13 @@ use crate::{
         revision_stylesheet::{RevisionStylesheet, RevisionStylesheetIn},
         Insertable,
     },
+    layout::{Layout, Role},
     Result,
 };
 
-/// Create routes for all static assets
-pub fn create_static_asset_routes(db: &Connection, rev_id: usize) -> Result<()> {
+/// Create routes for all static assets, the files of static and content directories that aren't
+/// pages.
+pub fn create_static_asset_routes(layout: &Layout, db: &Connection, rev_id: usize) -> Result<()> {
     #[derive(Deserialize, Debug)]
     struct Row {
         hash: String,
@@ -41,9 +40,13 @@ pub fn create_static_asset_routes(db: &Connection, rev_id: usize) -> Result<()>
         let rows = from_rows::<Row>(stmt.query(params![rev_id])?);
         for row in rows {
             let row = row?;
+            let route_path = match layout.asset_route_path(&row.path) {
+                Some(route_path) => route_path,
+                None => continue,
+            };
             insert_route(&RevisionRouteIn {
                 revision: rev_id,
-                route_path: row.path.trim_start_matches("static/"),
+                route_path: &route_path,
                 parent_route_path: None,
                 kind: RevisionRouteKind::StaticAsset,
                 hash: &row.hash,
@@ -59,21 +62,27 @@ pub fn create_static_asset_routes(db: &Connection, rev_id: usize) -> Result<()>
 }
 
 // Creates routes for pages
-pub fn create_page_routes(db: &Connection, rev_id: usize) -> Result<()> {
+pub fn create_page_routes(layout: &Layout, db: &Connection, rev_id: usize) -> Result<()> {
     let pages = Page::for_revision(db, rev_id)?;
 
     RevisionRoute::with_insert(db, |insert_route| {
         for page in &pages {
+            // Routed with the current layout, the stored route is from when the page was added.
+            let route_path = match layout.page_route_path(&page.path) {
+                Some(route_path) => route_path,
+                None => continue,
+            };
             insert_route(&RevisionRouteIn {
                 revision: rev_id,
                 kind: RevisionRouteKind::Page,
-                route_path: &page.route_path,
-                parent_route_path: do_parent_path(&page.route_path)
-                    .as_ref()
-                    .map(|s| -> &str { s }),
+                route_path: &route_path,
+                parent_route_path: do_parent_path(&route_path).as_ref().map(|s| -> &str { s }),
                 hash: &page.hash,
                 path: &page.path,
-                template: page.template.as_ref().map(|x| -> &str { x }),
+                template: page
+                    .template
+                    .as_deref()
+                    .or_else(|| layout.page_template(&page.path)),
             })?;
         }
         Ok(())
@@ -87,17 +96,8 @@ pub fn do_parent_path(path: &str) -> Option<String> {
     p.parent().map(|o| o.to_string_lossy().to_string())
 }
 
-pub fn to_route_path(path: &str) -> Result<Cow<str>> {
-    let route_path = path
-        .trim_start_matches("content")
-        .trim_end_matches("/index.md")
-        .trim_start_matches('/');
-    let ex_re = regex::Regex::new("[.][^.]+$")?;
-    let route_path = ex_re.replace(route_path, "");
-    Ok(route_path)
-}
-
 pub fn compile_stylesheets(config: &Config, db: &Connection, rev_id: usize) -> Result<()> {
+    let layout = config.layout();
     let sass_tmp_dir = config.cache_dir().join(format!("tmp-sass-{}", rev_id));
     let _deferred_remove = RemoveDirAllOnDrop {
         path: sass_tmp_dir.clone(),
@@ -109,7 +109,7 @@ pub fn compile_stylesheets(config: &Config, db: &Connection, rev_id: usize) -> R
         FROM input_files
         INNER JOIN revision_files
         ON revision_files.hash = input_files.hash AND revision_files.path = input_files.path
-        WHERE input_files.path REGEXP 'sass/[^.]+.scss'
+        WHERE input_files.path REGEXP '[.]scss$'
         AND revision_files.revision = ?1
     ",
     )?;
@@ -120,21 +120,34 @@ pub fn compile_stylesheets(config: &Config, db: &Connection, rev_id: usize) -> R
         contents: Vec<u8>,
     }
 
-    let rows = from_rows::<Row>(stmt.query(params![rev_id])?);
-    for row in rows {
+    // Every sass directory is merged into one, earlier directories win on conflicts.
+    let sass_dirs = layout.dirs_with_role(Role::Sass).collect::<Vec<_>>();
+    let mut files = Vec::new();
+    for row in from_rows::<Row>(stmt.query(params![rev_id])?) {
         let row = row?;
-        let mut dest_path = sass_tmp_dir.clone();
-        for tok in row.path.split('/') {
+        if let Some(resolved) = layout.resolve(&row.path) {
+            if let Some(priority) = sass_dirs.iter().position(|d| *d == resolved.dir) {
+                files.push((priority, resolved.rest.to_string(), row.contents));
+            }
+        }
+    }
+    files.sort_by_key(|(priority, ..)| std::cmp::Reverse(*priority));
+    for (_, rest, contents) in files {
+        let mut dest_path = sass_tmp_dir.join("sass");
+        for tok in rest.split('/') {
             dest_path.push(tok);
         }
         std::fs::create_dir_all(dest_path.parent().unwrap())?;
-        std::fs::write(&dest_path, &row.contents)?;
+        std::fs::write(&dest_path, &contents)?;
     }
 
-    let out = rsass::compile_scss_path(
-        &sass_tmp_dir.join("sass").join("style.scss"),
-        Default::default(),
-    )?;
+    let entry_point = sass_tmp_dir.join("sass").join("style.scss");
+    if !entry_point.exists() {
+        log::debug!("No style.scss, skipping stylesheet");
+        return Ok(());
+    }
+
+    let out = rsass::compile_scss_path(&entry_point, Default::default())?;
 
     let mut insert_revision_stylesheet = RevisionStylesheet::prepare_insert(db)?;
     insert_revision_stylesheet(&RevisionStylesheetIn {
@@ -146,7 +159,7 @@ pub fn compile_stylesheets(config: &Config, db: &Connection, rev_id: usize) -> R
     let mut insert_route = RevisionRoute::prepare_insert(db)?;
     insert_route(&RevisionRouteIn {
         revision: rev_id,
-        route_path: "style.css",
+        route_path: &layout.stylesheet_route_path(),
         parent_route_path: None,
         kind: RevisionRouteKind::Stylesheet,
         hash: "",
@@ -163,6 +176,9 @@ struct RemoveDirAllOnDrop {
 
 impl Drop for RemoveDirAllOnDrop {
     fn drop(&mut self) {
+        if !self.path.exists() {
+            return;
+        }
         if let Err(e) = std::fs::remove_dir_all(&self.path) {
             log::warn!("Could not remove temporary dir: {}", e);
         }
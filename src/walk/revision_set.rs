@@ -27,8 +27,7 @@ impl RevisionSet {
         P: AsRef<str>,
     {
         self.0
-            .get(&(hash.as_ref().to_string(), path.as_ref().to_string()))
-            .is_some()
+            .contains(&(hash.as_ref().to_string(), path.as_ref().to_string()))
     }
 
     /// Adds a hapa to the set.
@@ -43,13 +42,12 @@ impl RevisionSet {
         self.0.insert((hash.into(), path.into()))
     }
 
-    /// Removes all hapa matching the given path
+    /// Removes all hapa matching the given path, either the file itself or, for a directory,
+    /// everything under it. Paths are compared by component, so removing `static/img` leaves
+    /// `static/img2.png` alone.
     pub fn remove_by_path<S: AsRef<str>>(&mut self, path: S) -> &mut Self {
-        let temp = std::mem::replace(self, Self::new());
-        *self = temp
-            .into_iter()
-            .filter(|(_, p)| !p.contains(path.as_ref()))
-            .collect();
+        let path = path.as_ref().trim_end_matches('/');
+        self.0.retain(|(_, p)| !is_same_or_under(p, path));
 
         self
     }
@@ -65,6 +63,14 @@ impl RevisionSet {
     }
 }
 
+/// Whether `path` is `dir` or inside of it.
+fn is_same_or_under(path: &str, dir: &str) -> bool {
+    match path.strip_prefix(dir) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
 impl From<Vec<(String, String)>> for RevisionSet {
     fn from(v: Vec<(String, String)>) -> Self {
         v.into_iter().collect()
@@ -96,3 +102,61 @@ impl FromFallibleIterator<(String, String)> for RevisionSet {
         HashSet::from_fallible_iter(it).map(Self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::RevisionSet;
+
+    fn set(paths: &[&str]) -> RevisionSet {
+        paths
+            .iter()
+            .map(|p| ("0000000000000000".to_string(), p.to_string()))
+            .collect()
+    }
+
+    fn paths(set: RevisionSet) -> Vec<String> {
+        let mut paths = set.into_iter().map(|(_, p)| p).collect::<Vec<_>>();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn removes_exact_file() {
+        let mut s = set(&["content/post.md", "content/post.md.bak", "content/other.md"]);
+        s.remove_by_path("content/post.md");
+        assert_eq!(paths(s), ["content/other.md", "content/post.md.bak"]);
+    }
+
+    #[test]
+    fn removes_directory_subtree() {
+        let mut s = set(&[
+            "content/post/index.md",
+            "content/post/img/a.png",
+            "content/post-2.md",
+            "content/posts/first.md",
+        ]);
+        s.remove_by_path("content/post");
+        assert_eq!(paths(s), ["content/post-2.md", "content/posts/first.md"]);
+    }
+
+    #[test]
+    fn trailing_slash_removes_directory() {
+        let mut s = set(&["static/img/a.png", "static/img2/b.png"]);
+        s.remove_by_path("static/img/");
+        assert_eq!(paths(s), ["static/img2/b.png"]);
+    }
+
+    #[test]
+    fn keeps_similarly_named_siblings() {
+        let mut s = set(&["static/img", "static/img2/b.png", "static/img.png"]);
+        s.remove_by_path("static/img");
+        assert_eq!(paths(s), ["static/img.png", "static/img2/b.png"]);
+    }
+
+    #[test]
+    fn does_not_match_in_the_middle() {
+        let mut s = set(&["content/static/a.md", "static/a.png"]);
+        s.remove_by_path("static");
+        assert_eq!(paths(s), ["content/static/a.md"]);
+    }
+}